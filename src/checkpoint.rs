@@ -0,0 +1,247 @@
+use crate::cli::config::{Config, Dataset, Destination, OutputConfig, Range};
+use crate::cli::signature::keccak_hex;
+use crate::export::export::sync_parent;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Progress of a run, saved next to its output after every write so an interrupted
+/// run can continue with `--resume`. Written files are appended to a log beside it,
+/// so saving stays the same size however many chunks were written.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Checkpoint {
+    pub path: PathBuf,
+    pub query_hash: String,
+    // last block of the last chunk fully written
+    pub last_block: Option<u64>,
+    pub files: HashSet<String>,
+    // whether the file log belongs to this run, a new run starts it over
+    appending: bool,
+}
+
+impl Checkpoint {
+    /// Empty checkpoint of the run of `config`, none when streaming to stdout
+    pub fn new(config: &Config) -> Option<Checkpoint> {
+        Some(Checkpoint {
+            path: checkpoint_path(config.dataset, &config.output)?,
+            query_hash: query_hash(config),
+            last_block: None,
+            files: HashSet::new(),
+            appending: false,
+        })
+    }
+
+    /// Checkpoint of an earlier run of the same query as `config`, if one was saved
+    pub fn load(config: &Config) -> Result<Option<Checkpoint>, Error> {
+        let path = match checkpoint_path(config.dataset, &config.output) {
+            Some(path) if path.exists() => path,
+            _ => return Ok(None),
+        };
+        let invalid = |message: &str| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), message),
+            )
+        };
+        let value: Value = serde_json::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| invalid(&e.to_string()))?;
+        let saved_hash = value["queryHash"]
+            .as_str()
+            .ok_or_else(|| invalid("no query hash"))?;
+        if saved_hash != query_hash(config) {
+            return Err(invalid(
+                "checkpoint of a different query, run without --resume to start over",
+            ));
+        }
+        let files: HashSet<String> = match fs::read_to_string(log_path(&path)) {
+            Ok(log) => log.lines().map(str::to_owned).collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        if let Some(missing) = files.iter().find(|file| !Path::new(file).exists()) {
+            return Err(invalid(&format!("written file {} is missing", missing)));
+        }
+        Ok(Some(Checkpoint {
+            query_hash: saved_hash.to_owned(),
+            last_block: value["lastBlock"].as_u64(),
+            files,
+            path,
+            appending: true,
+        }))
+    }
+
+    /// Records the chunk ending at `end` as written to `files` and saves the checkpoint
+    pub fn record(&mut self, end: u64, files: Vec<String>) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // files are logged before the block that needs them
+        let new: Vec<String> = files
+            .into_iter()
+            .filter(|file| !self.files.contains(file))
+            .collect();
+        if !new.is_empty() || !self.appending {
+            let mut log = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(self.appending)
+                .truncate(!self.appending)
+                .open(log_path(&self.path))?;
+            for file in &new {
+                writeln!(log, "{}", file)?;
+            }
+            log.sync_data()?;
+            self.files.extend(new);
+            self.appending = true;
+        }
+        self.last_block = Some(end);
+        let value = json!({
+            "queryHash": self.query_hash,
+            "lastBlock": self.last_block,
+        });
+        // written aside and renamed, a kill never leaves half a checkpoint
+        let temp = temp_path(&self.path);
+        let mut file = File::create(&temp)?;
+        file.write_all(serde_json::to_string_pretty(&value)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, &self.path)?;
+        sync_parent(&self.path)
+    }
+
+    /// Parts of `ranges` after the last written block
+    pub fn remaining(&self, ranges: Vec<Range>) -> Vec<Range> {
+        let last_block = match self.last_block {
+            Some(last_block) => last_block,
+            None => return ranges,
+        };
+        ranges
+            .into_iter()
+            .filter(|range| range.end > last_block)
+            .map(|range| Range {
+                start: range.start.max(last_block + 1),
+                end: range.end,
+            })
+            .collect()
+    }
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    path.with_extension("json.tmp")
+}
+
+/// Where the checkpoint of `dataset` is written before it is renamed into place
+pub fn checkpoint_temp_path(dataset: Dataset, output: &OutputConfig) -> Option<PathBuf> {
+    checkpoint_path(dataset, output).map(|path| temp_path(&path))
+}
+
+// written files, one per line
+fn log_path(path: &Path) -> PathBuf {
+    path.with_extension("files")
+}
+
+// one checkpoint per dataset in a directory or next to a database file
+fn checkpoint_path(dataset: Dataset, output: &OutputConfig) -> Option<PathBuf> {
+    let name = dataset.name();
+    match &output.destination {
+        Destination::Directory(dir) => {
+            Some(Path::new(dir).join(format!(".{}.checkpoint.json", name)))
+        }
+        Destination::Database(path) => {
+            Some(PathBuf::from(format!("{}.{}.checkpoint.json", path, name)))
+        }
+        Destination::Stdout => None,
+    }
+}
+
+/// Hash of everything deciding what a run writes, runs resume only into equal queries
+pub fn query_hash(config: &Config) -> String {
+    let OutputConfig {
+        format,
+        nested,
+        filename_template,
+        partition,
+        columns,
+        metadata,
+        ..
+    } = &config.output;
+    // the metadata carries the network, archive, range, sorted options and filter
+    let query = format!(
+        "{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}",
+        config.dataset,
+        config.fields,
+        config.relations,
+        config.chunk_size,
+        format,
+        nested,
+        filename_template,
+        partition,
+        columns,
+        metadata,
+    );
+    keccak_hex(&query, 32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Checkpoint;
+    use crate::cli::config::{test_opts, Config, Range};
+    use crate::cli::opts::Opts;
+    use anyhow::Result;
+    use std::fs;
+
+    fn blocks_config(dir: &str, fields: &[&str]) -> Result<Config> {
+        Opts {
+            fields: Some(fields.iter().map(|field| field.to_string()).collect()),
+            output_dir: Some(dir.to_owned()),
+            resume: true,
+            ..test_opts("blocks", "0:99")
+        }
+        .try_into()
+    }
+
+    #[test]
+    fn test_checkpoint() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let dir_name = dir.to_string_lossy().into_owned();
+        let config = blocks_config(&dir_name, &["number"])?;
+        assert_eq!(Checkpoint::load(&config)?, None);
+
+        let file = dir.join("ethereum__blocks__0_to_49.parquet");
+        fs::write(&file, "")?;
+        let mut checkpoint = Checkpoint::new(&config).unwrap();
+        checkpoint.record(49, vec![file.to_string_lossy().into_owned()])?;
+        let loaded = Checkpoint::load(&config)?.unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(
+            loaded.remaining(vec![Range { start: 0, end: 99 }]),
+            vec![Range { start: 50, end: 99 }]
+        );
+
+        // files are logged once, the checkpoint itself holds no list
+        let mut resumed = loaded;
+        resumed.record(99, vec![file.to_string_lossy().into_owned()])?;
+        let saved = fs::read_to_string(dir.join(".blocks.checkpoint.json"))?;
+        assert!(!saved.contains("files"));
+        let log = fs::read_to_string(dir.join(".blocks.checkpoint.files"))?;
+        assert_eq!(log.lines().count(), 1);
+
+        // other fields make another query
+        assert!(Checkpoint::load(&blocks_config(&dir_name, &["number", "hash"])?).is_err());
+        // as do written files removed since
+        fs::remove_file(&file)?;
+        assert!(Checkpoint::load(&config).is_err());
+
+        // a new run starts the log over
+        let mut fresh = Checkpoint::new(&config).unwrap();
+        fresh.record(49, Vec::new())?;
+        assert_eq!(
+            fs::read_to_string(dir.join(".blocks.checkpoint.files"))?,
+            ""
+        );
+        assert!(Checkpoint::load(&config)?.is_some());
+        Ok(())
+    }
+}
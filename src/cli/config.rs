@@ -1,9 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::time::Duration;
 
+use crate::cli::option_file::load_option_values;
 use crate::cli::opts::Opts;
-use anyhow::{anyhow, Context, Ok, Result};
+use crate::cli::signature::{signature_hash, split_top_level, Abi};
+use crate::export::fields::DATE_FIELD;
+use crate::export::filter::RowFilter;
+use crate::export::sqlite::table_key;
+use anyhow::{anyhow, Ok, Result};
+use polars::export::arrow::io::parquet::write::{CompressionOptions, GzipLevel, ZstdLevel};
 
-#[derive(Debug, PartialEq)]
+const DEFAULT_ARCHIVE: &str = "https://v2.archive.subsquid.io/network/ethereum-mainnet";
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+const DEFAULT_READ_TIMEOUT: u64 = 60;
+const DEFAULT_FILENAME_TEMPLATE: &str = "{network}__{dataset}__{start}_to_{end}";
+const DEFAULT_PARTITION_TEMPLATE: &str = "part-{start}_to_{end}";
+const DEFAULT_BUCKET_SIZE: u64 = 1_000_000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Range {
     pub start: u64,
     pub end: u64,
@@ -14,28 +29,303 @@ pub enum Dataset {
     Blocks,
     Transactions,
     Logs,
+    // only fetched as related data of transactions and logs
+    Traces,
+    StateDiffs,
+}
+
+impl Dataset {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dataset::Blocks => "blocks",
+            Dataset::Transactions => "transactions",
+            Dataset::Logs => "logs",
+            Dataset::Traces => "traces",
+            Dataset::StateDiffs => "state_diffs",
+        }
+    }
 }
+
+/// Related data requested alongside the matched items, written to sibling files
+#[derive(Debug, PartialEq, Clone)]
+pub struct Relation {
+    pub dataset: Dataset,
+    pub fields: Vec<String>,
+}
+
 #[derive(Debug)]
 pub struct Config {
     pub dataset: Dataset,
     pub range: Range,
+    pub chunk_size: Option<u64>,
     pub fields: Vec<String>,
     pub options: HashMap<String, Vec<String>>,
+    pub relations: Vec<Relation>,
+    pub filter: Option<RowFilter>,
+    pub output: OutputConfig,
+    // continue after the last block of the checkpoint of an interrupted run
+    pub resume: bool,
+    pub archive: String,
+    pub http: HttpConfig,
+}
+
+/// Settings for the HTTP client shared by all archive requests
+#[derive(Clone, PartialEq)]
+pub struct HttpConfig {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub proxy: Option<String>,
+    pub bearer_token: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub compression: bool,
+    pub user_agent: String,
+}
+
+// tokens and header values are credentials, keep them out of the config printout
+impl fmt::Debug for HttpConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpConfig")
+            .field("connect_timeout", &self.connect_timeout)
+            .field("read_timeout", &self.read_timeout)
+            .field("proxy", &self.proxy)
+            .field("bearer_token", &self.bearer_token.as_ref().map(|_| "***"))
+            .field(
+                "headers",
+                &self
+                    .headers
+                    .iter()
+                    .map(|(name, _)| (name.as_str(), "***"))
+                    .collect::<Vec<_>>(),
+            )
+            .field("compression", &self.compression)
+            .field("user_agent", &self.user_agent)
+            .finish()
+    }
+}
+
+/// File format chunks are written in
+#[derive(Debug, PartialEq, Clone)]
+pub enum OutputFormat {
+    Parquet(ParquetOptions),
+    // hashes, addresses and hex quantities stay 0x prefixed text so
+    // spreadsheets do not round them, numeric fields stay plain integers
+    Csv(CsvOptions),
+    Json,
+    Ndjson,
+    // arrow ipc file and stream formats
+    Arrow,
+    ArrowStream,
+    // tables per dataset in a single database file
+    Sqlite,
+    Duckdb,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub header: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParquetOptions {
+    pub compression: CompressionOptions,
+    // rows per row group, the whole chunk when unset
+    pub row_group_size: Option<usize>,
+    pub dictionary: bool,
+    pub statistics: bool,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Parquet(_) => "parquet",
+            OutputFormat::Csv(_) => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Arrow => "arrow",
+            OutputFormat::ArrowStream => "arrows",
+            OutputFormat::Sqlite => "db",
+            OutputFormat::Duckdb => "duckdb",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Destination {
+    Directory(String),
+    Stdout,
+    Database(String),
+}
+
+/// Hive-style directory level below `dataset=<name>`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Partition {
+    Date,
+    Month,
+    BlockBucket(u64),
+}
+
+/// What to do with block ranges already written to the output directory
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum WriteMode {
+    // fetch the whole range and replace existing files
+    #[default]
+    Overwrite,
+    // fetch only the blocks after the last existing file
+    Append,
+    // fetch only the blocks no existing file covers
+    SkipExisting,
+}
+
+/// How hashes, addresses and input are stored, `--hex` or `--binary`
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Encoding {
+    // 0x prefixed hex text, readable in every format
+    #[default]
+    Hex,
+    // fixed size or variable binary columns, parquet and arrow only
+    Binary,
+}
+
+/// How 256-bit quantities such as value and gas prices are stored
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Quantity {
+    // decimal text, exact in every format
+    #[default]
+    String,
+    Float,
+    // decimal128 with the given scale, 18 gives ether for wei values
+    Decimal(u8),
+    // `<field>_high` and `<field>_low` 128-bit halves as 16 byte big-endian binary
+    Split,
+}
+
+/// Column types of hex bytes, quantities and timestamps
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct ColumnTypes {
+    pub encoding: Encoding,
+    pub quantity: Quantity,
+    // block timestamps as unix seconds instead of UTC datetimes in parquet and arrow
+    pub raw_timestamps: bool,
+    // extra `date` column of the block day on every row
+    pub date: bool,
+}
+
+/// Settings for the files written by the write loop
+#[derive(Debug, PartialEq, Clone)]
+pub struct OutputConfig {
+    pub format: OutputFormat,
+    pub destination: Destination,
+    pub nested: bool,
+    // transaction fields embedded in nested blocks, with `--include-transaction`
+    pub nested_transactions: Option<Vec<String>>,
+    pub network: String,
+    pub filename_template: String,
+    pub partition: Option<Partition>,
+    pub mode: WriteMode,
+    pub columns: ColumnTypes,
+    // run details embedded in every parquet file, next to its dataset, fields and range
+    pub metadata: Vec<(String, String)>,
+}
+
+impl OutputConfig {
+    /// File name for the blocks `start` to `end` of `dataset`, extension included
+    pub fn file_name(&self, dataset: Dataset, start: u64, end: u64) -> String {
+        let name = self
+            .filename_template
+            .replace("{network}", &self.network)
+            .replace("{dataset}", dataset.name())
+            .replace("{start}", &start.to_string())
+            .replace("{end}", &end.to_string());
+        format!("{}.{}", name, self.format.extension())
+    }
+
+    /// Block range of a `dataset` file named by `file_name`, if the name fits the template
+    pub fn file_range(&self, dataset: Dataset, file_name: &str) -> Option<Range> {
+        let name = file_name.strip_suffix(&format!(".{}", self.format.extension()))?;
+        let template = self
+            .filename_template
+            .replace("{network}", &self.network)
+            .replace("{dataset}", dataset.name());
+        let (mut start, mut end) = (None, None);
+        let mut pieces = template.split('{');
+        let mut rest = name.strip_prefix(pieces.next()?)?;
+        for piece in pieces {
+            let (placeholder, literal) = piece.split_once('}')?;
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let number = rest[..digits].parse::<u64>().ok()?;
+            match placeholder {
+                "start" => start = Some(number),
+                "end" => end = Some(number),
+                _ => return None,
+            }
+            rest = rest[digits..].strip_prefix(literal)?;
+        }
+        match (start, end, rest.is_empty()) {
+            (Some(start), Some(end), true) if start <= end => Some(Range { start, end }),
+            _ => None,
+        }
+    }
 }
 
 impl TryFrom<Opts> for Config {
     type Error = anyhow::Error;
     fn try_from(opts: Opts) -> Result<Self> {
-        let dataset = get_dataset(opts.dataset)?;
+        let dataset = get_dataset(opts.dataset.clone())?;
+        let relations = get_relations(&opts, dataset)?;
+        let fields = get_fields(opts.fields.clone(), dataset)?;
+        let mut output = get_output(&opts, dataset, &fields, &relations)?;
         let range = get_range(opts.range)?.try_into()?;
-        let fields = get_fields(opts.fields, dataset)?;
-        let options = get_options(opts.options, dataset.clone())?;
+        let chunk_size = match opts.chunk_size {
+            Some(0) => return Err(anyhow!("Chunk size must be at least one block")),
+            chunk_size => chunk_size,
+        };
+        let abi = opts.abi.as_deref().map(Abi::from_file).transpose()?;
+        let options = get_options(opts.options, dataset, abi.as_ref())?;
+        let filter = get_filter(opts.filter.as_deref(), &output_columns(&fields, &output))?;
+        if opts.resume && output.destination == Destination::Stdout {
+            return Err(anyhow!("--resume needs output files or a database"));
+        }
+        let archive = opts
+            .archive
+            .map(|archive| archive.trim_end_matches('/').to_owned())
+            .unwrap_or_else(|| DEFAULT_ARCHIVE.to_owned());
+        output.metadata = run_metadata(
+            &output.network,
+            &archive,
+            &range,
+            &options,
+            opts.filter.as_deref(),
+        );
+        let http = HttpConfig {
+            connect_timeout: Duration::from_secs(
+                opts.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            ),
+            read_timeout: Duration::from_secs(opts.read_timeout.unwrap_or(DEFAULT_READ_TIMEOUT)),
+            proxy: opts.proxy,
+            bearer_token: opts.bearer_token,
+            headers: get_headers(opts.headers)?,
+            compression: !opts.no_compression,
+            user_agent: opts.user_agent.unwrap_or_else(|| {
+                format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+            }),
+        };
 
         Ok(Config {
             dataset,
             range,
+            chunk_size,
             fields,
             options,
+            relations,
+            filter,
+            output,
+            resume: opts.resume,
+            archive,
+            http,
         })
     }
 }
@@ -69,6 +359,33 @@ fn get_range(range: Option<String>) -> Result<Vec<String>> {
     }
 }
 
+// selected fields and the derived columns written next to them
+fn output_columns(fields: &[String], output: &OutputConfig) -> Vec<String> {
+    let mut columns = fields.to_vec();
+    if output.columns.date {
+        columns.push(DATE_FIELD.to_owned());
+    }
+    columns
+}
+
+// columns are checked here so a typo fails before anything is fetched
+fn get_filter(filter: Option<&str>, columns: &[String]) -> Result<Option<RowFilter>> {
+    let filter = match filter {
+        Some(filter) if !filter.trim().is_empty() => RowFilter::parse(filter)?,
+        _ => return Ok(None),
+    };
+    for column in filter.columns() {
+        if !columns.iter().any(|field| field == column) {
+            return Err(anyhow!(
+                "Filter column {} is not one of the output columns {:?}",
+                column,
+                columns
+            ));
+        }
+    }
+    Ok(Some(filter))
+}
+
 fn get_fields(fields: Option<Vec<String>>, dataset: Dataset) -> Result<Vec<String>> {
     match dataset {
         Dataset::Blocks => {
@@ -98,14 +415,45 @@ fn get_fields(fields: Option<Vec<String>>, dataset: Dataset) -> Result<Vec<Strin
         Dataset::Logs => {
             if fields.is_none() {
                 return Ok(vec![
-                    "hash".to_owned(),
+                    "transactionHash".to_owned(),
                     "logIndex".to_owned(),
                     "transactionIndex".to_owned(),
                     "address".to_owned(),
                     "data".to_owned(),
+                    "topic0".to_owned(),
                 ]);
             };
             verify_log_fields(fields.unwrap())
+        }
+        Dataset::Traces => {
+            if fields.is_none() {
+                return Ok(vec![
+                    "transactionIndex".to_owned(),
+                    "traceAddress".to_owned(),
+                    "type".to_owned(),
+                    "subtraces".to_owned(),
+                    "error".to_owned(),
+                    "from".to_owned(),
+                    "to".to_owned(),
+                    "value".to_owned(),
+                    "input".to_owned(),
+                    "gasUsed".to_owned(),
+                ]);
+            };
+            Err(anyhow!("Traces only have the default fields"))
+        }
+        Dataset::StateDiffs => {
+            if fields.is_none() {
+                return Ok(vec![
+                    "transactionIndex".to_owned(),
+                    "address".to_owned(),
+                    "key".to_owned(),
+                    "kind".to_owned(),
+                    "prev".to_owned(),
+                    "next".to_owned(),
+                ]);
+            };
+            Err(anyhow!("State diffs only have the default fields"))
         } // match fields {
           //     Some(fields) => verify_fields(fields.trim().split(" ").map(String::from).collect()),
           //     None => Err(anyhow!("No fields specified")),
@@ -188,18 +536,25 @@ fn verify_block_fields(fields: Vec<String>) -> Result<Vec<String>> {
 
 fn verify_log_fields(fields: Vec<String>) -> Result<Vec<String>> {
     let valid_fields: &[&str] = &[
-        "hash",
         "logIndex",
         "transactionIndex",
+        "transactionHash",
         "address",
         "data",
-        //"topics",
+        "topic0",
+        "topic1",
+        "topic2",
+        "topic3",
+        "blockNumber",
     ];
 
     fields
         .into_iter()
         .map(|field| {
-            if valid_fields.contains(&field.as_str()) {
+            // `hash` named the transaction hash of a log before `transactionHash`
+            if field == "hash" {
+                Ok("transactionHash".to_owned())
+            } else if valid_fields.contains(&field.as_str()) {
                 Ok(field)
             } else {
                 Err(anyhow!("Invalid field: {}", field))
@@ -208,6 +563,413 @@ fn verify_log_fields(fields: Vec<String>) -> Result<Vec<String>> {
         .collect()
 }
 
+fn get_relations(opts: &Opts, dataset: Dataset) -> Result<Vec<Relation>> {
+    let requested = [
+        (opts.include_transaction, Dataset::Transactions),
+        (opts.include_logs, Dataset::Logs),
+        (opts.include_traces, Dataset::Traces),
+        (opts.include_state_diffs, Dataset::StateDiffs),
+    ];
+    let valid_relations: &[Dataset] = match dataset {
+        Dataset::Blocks => &[Dataset::Transactions],
+        Dataset::Transactions => &[Dataset::Logs, Dataset::Traces, Dataset::StateDiffs],
+        Dataset::Logs => &[Dataset::Transactions, Dataset::Traces],
+        _ => &[],
+    };
+
+    requested
+        .into_iter()
+        .filter(|(include, _)| *include)
+        .map(|(_, relation)| {
+            if !valid_relations.contains(&relation) {
+                return Err(anyhow!(
+                    "Invalid relation: {} can not be included with {}",
+                    relation.name(),
+                    dataset.name()
+                ));
+            }
+            // related rows carry the block number so they can be joined back
+            let mut fields = vec!["blockNumber".to_owned()];
+            fields.extend(get_fields(None, relation)?);
+            Ok(Relation {
+                dataset: relation,
+                fields,
+            })
+        })
+        .collect()
+}
+
+fn get_headers(headers: Vec<String>) -> Result<Vec<(String, String)>> {
+    headers
+        .into_iter()
+        .map(|header| match header.split_once(':') {
+            Some((name, value)) if !name.trim().is_empty() => {
+                Ok((name.trim().to_owned(), value.trim().to_owned()))
+            }
+            _ => Err(anyhow!(
+                "Invalid header: {}, expected `Name: value`",
+                header
+            )),
+        })
+        .collect()
+}
+
+fn get_output(
+    opts: &Opts,
+    dataset: Dataset,
+    fields: &[String],
+    relations: &[Relation],
+) -> Result<OutputConfig> {
+    let format = get_format(opts)?;
+    let database = matches!(format, OutputFormat::Sqlite | OutputFormat::Duckdb);
+    let destination = match (database, opts.output.as_deref()) {
+        (true, Some(path)) if path != "-" => Destination::Database(path.to_owned()),
+        (true, _) => return Err(anyhow!("{:?} needs --output <file>", format)),
+        (_, None) => {
+            Destination::Directory(opts.output_dir.clone().unwrap_or_else(|| ".".to_owned()))
+        }
+        (_, Some("-")) => Destination::Stdout,
+        (_, Some(output)) => return Err(anyhow!("Invalid output: {}, use `-` for stdout", output)),
+    };
+    if format == OutputFormat::Sqlite {
+        // rows are upserted by key, so every table needs its key columns
+        let tables = relations
+            .iter()
+            .map(|relation| (relation.dataset, relation.fields.as_slice()));
+        for (dataset, fields) in std::iter::once((dataset, fields)).chain(tables) {
+            if table_key(dataset, fields).is_none() {
+                return Err(anyhow!(
+                    "{} table needs key fields such as {}",
+                    dataset.name(),
+                    match dataset {
+                        Dataset::Blocks => "number or hash",
+                        Dataset::Transactions => "hash or blockNumber and transactionIndex",
+                        Dataset::Logs => "blockNumber or transactionHash, and logIndex",
+                        Dataset::Traces => "blockNumber, transactionIndex and traceAddress",
+                        Dataset::StateDiffs => "blockNumber, transactionIndex, address and key",
+                    }
+                ));
+            }
+        }
+    }
+    let partition = get_partition(opts.partition_by.as_deref())?;
+    let filename_template = match (&opts.filename_template, partition) {
+        (Some(template), _) => template.clone(),
+        (None, Some(_)) => DEFAULT_PARTITION_TEMPLATE.to_owned(),
+        (None, None) => DEFAULT_FILENAME_TEMPLATE.to_owned(),
+    };
+    match destination {
+        Destination::Directory(_) => {
+            // partitioned datasets get their own directories, nested blocks embed
+            // their transactions
+            let shared_names = !relations.is_empty() && partition.is_none() && !opts.nested;
+            verify_filename_template(&filename_template, shared_names)?
+        }
+        _ if opts.output_dir.is_some()
+            || opts.filename_template.is_some()
+            || partition.is_some() =>
+        {
+            return Err(anyhow!(
+                "--output-dir, --filename-template and --partition-by only apply to file formats"
+            ))
+        }
+        _ => {}
+    }
+    if destination == Destination::Stdout {
+        if matches!(format, OutputFormat::Parquet(_) | OutputFormat::Arrow) {
+            return Err(anyhow!(
+                "{} files can not be streamed to stdout, use arrow-stream",
+                format.extension()
+            ));
+        }
+        if !relations.is_empty() && !opts.nested {
+            return Err(anyhow!("Related data can not be streamed to stdout"));
+        }
+    }
+    let mode = get_mode(opts.mode.as_deref())?;
+    if mode != WriteMode::Overwrite {
+        if !matches!(destination, Destination::Directory(_)) {
+            return Err(anyhow!(
+                "--mode append and skip-existing only apply to file formats"
+            ));
+        }
+        if !filename_template.contains("{end}") {
+            return Err(anyhow!(
+                "--mode append and skip-existing need {{end}} in the filename template"
+            ));
+        }
+    }
+    let columns = ColumnTypes {
+        encoding: get_encoding(opts.hex, opts.binary),
+        quantity: get_quantity(opts.u256.as_deref())?,
+        raw_timestamps: opts.raw_timestamps,
+        date: opts.date_column,
+    };
+    let arrow_types = columns.encoding == Encoding::Binary
+        || matches!(columns.quantity, Quantity::Decimal(_) | Quantity::Split);
+    if arrow_types
+        && !matches!(
+            format,
+            OutputFormat::Parquet(_) | OutputFormat::Arrow | OutputFormat::ArrowStream
+        )
+    {
+        return Err(anyhow!(
+            "--binary, --u256 decimal and --u256 split need --format parquet, arrow or arrow-stream"
+        ));
+    }
+    if opts.nested {
+        let json = matches!(format, OutputFormat::Json | OutputFormat::Ndjson);
+        if !json || dataset != Dataset::Blocks || opts.filter.is_some() {
+            return Err(anyhow!(
+                "--nested needs --format json or ndjson, the blocks dataset and no --where"
+            ));
+        }
+    }
+    Ok(OutputConfig {
+        format,
+        destination,
+        nested: opts.nested,
+        nested_transactions: relations
+            .iter()
+            .find(|relation| opts.nested && relation.dataset == Dataset::Transactions)
+            .map(|relation| {
+                // the block number is the header of the nested transactions
+                relation
+                    .fields
+                    .iter()
+                    .filter(|field| *field != "blockNumber")
+                    .cloned()
+                    .collect()
+            }),
+        network: network_name(opts.archive.as_deref().unwrap_or(DEFAULT_ARCHIVE)),
+        filename_template,
+        partition,
+        mode,
+        columns,
+        metadata: Vec::new(),
+    })
+}
+
+fn run_metadata(
+    network: &str,
+    archive: &str,
+    range: &Range,
+    options: &HashMap<String, Vec<String>>,
+    filter: Option<&str>,
+) -> Vec<(String, String)> {
+    // sorted so equal queries carry equal metadata
+    let options: BTreeMap<&String, &Vec<String>> = options.iter().collect();
+    let mut metadata = vec![
+        ("network".to_owned(), network.to_owned()),
+        ("archive".to_owned(), archive.to_owned()),
+        (
+            "requested_range".to_owned(),
+            format!("{}:{}", range.start, range.end),
+        ),
+        (
+            "options".to_owned(),
+            serde_json::to_string(&options).unwrap_or_default(),
+        ),
+    ];
+    if let Some(filter) = filter {
+        metadata.push(("where".to_owned(), filter.to_owned()));
+    }
+    metadata.push((
+        "version".to_owned(),
+        format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+    ));
+    metadata
+}
+
+fn get_mode(mode: Option<&str>) -> Result<WriteMode> {
+    match mode {
+        None | Some("overwrite") => Ok(WriteMode::Overwrite),
+        Some("append") => Ok(WriteMode::Append),
+        Some("skip-existing") => Ok(WriteMode::SkipExisting),
+        Some(mode) => Err(anyhow!(
+            "Invalid mode: {}, expected append, overwrite or skip-existing",
+            mode
+        )),
+    }
+}
+
+// the flags conflict, hex is the default when neither is given
+fn get_encoding(hex: bool, binary: bool) -> Encoding {
+    match (hex, binary) {
+        (false, true) => Encoding::Binary,
+        _ => Encoding::Hex,
+    }
+}
+
+fn get_quantity(quantity: Option<&str>) -> Result<Quantity> {
+    let quantity = match quantity {
+        Some(quantity) => quantity,
+        None => return Ok(Quantity::default()),
+    };
+    match quantity.split_once(':') {
+        None if quantity == "string" => Ok(Quantity::String),
+        None if quantity == "f64" => Ok(Quantity::Float),
+        None if quantity == "decimal" => Ok(Quantity::Decimal(0)),
+        None if quantity == "split" => Ok(Quantity::Split),
+        Some(("decimal", scale)) => match scale.parse::<u8>().ok() {
+            Some(scale) if scale <= 38 => Ok(Quantity::Decimal(scale)),
+            _ => Err(anyhow!(
+                "Invalid decimal scale: {}, expected 0 to 38",
+                scale
+            )),
+        },
+        _ => Err(anyhow!(
+            "Invalid u256 format: {}, expected string, f64, decimal[:scale] or split",
+            quantity
+        )),
+    }
+}
+
+fn get_partition(partition: Option<&str>) -> Result<Option<Partition>> {
+    let partition = match partition {
+        Some(partition) => partition,
+        None => return Ok(None),
+    };
+    match partition.split_once(':') {
+        None if partition == "date" => Ok(Some(Partition::Date)),
+        None if partition == "month" => Ok(Some(Partition::Month)),
+        None if partition == "block_bucket" => {
+            Ok(Some(Partition::BlockBucket(DEFAULT_BUCKET_SIZE)))
+        }
+        Some(("block_bucket", size)) => match size.parse::<u64>().ok() {
+            Some(size) if size > 0 => Ok(Some(Partition::BlockBucket(size))),
+            _ => Err(anyhow!("Invalid block bucket size: {}", size)),
+        },
+        _ => Err(anyhow!(
+            "Invalid partition: {}, expected date, month or block_bucket[:<blocks>]",
+            partition
+        )),
+    }
+}
+
+// every file needs a distinct name, and related datasets in the same directory share the block range
+fn verify_filename_template(template: &str, shared_names: bool) -> Result<()> {
+    let unknown = ["{network}", "{dataset}", "{start}", "{end}"]
+        .iter()
+        .fold(template.to_owned(), |rest, placeholder| {
+            rest.replace(placeholder, "")
+        });
+    if unknown.contains(['{', '}', '/']) {
+        return Err(anyhow!("Invalid filename template: {}", template));
+    }
+    if !template.contains("{start}") {
+        return Err(anyhow!("Filename template {} needs {{start}}", template));
+    }
+    if shared_names && !template.contains("{dataset}") {
+        return Err(anyhow!(
+            "Filename template {} needs {{dataset}} to write related data",
+            template
+        ));
+    }
+    Ok(())
+}
+
+// `.../network/ethereum-mainnet` is written as `ethereum`
+fn network_name(archive: &str) -> String {
+    let name = archive
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    name.strip_suffix("-mainnet").unwrap_or(name).to_owned()
+}
+
+fn get_format(opts: &Opts) -> Result<OutputFormat> {
+    let format = match opts.format.as_deref().unwrap_or("parquet") {
+        "parquet" => OutputFormat::Parquet(ParquetOptions {
+            compression: get_compression(opts.compression.as_deref())?,
+            row_group_size: match opts.row_group_size {
+                Some(0) => return Err(anyhow!("Row group size must be positive")),
+                size => size,
+            },
+            dictionary: opts.dictionary,
+            statistics: !opts.no_statistics,
+        }),
+        "csv" => OutputFormat::Csv(CsvOptions {
+            delimiter: get_csv_char(opts.csv_delimiter.as_deref(), b',')?,
+            quote: get_csv_char(opts.csv_quote.as_deref(), b'"')?,
+            header: !opts.no_header,
+        }),
+        "json" => OutputFormat::Json,
+        "ndjson" => OutputFormat::Ndjson,
+        "arrow" => OutputFormat::Arrow,
+        "arrow-stream" => OutputFormat::ArrowStream,
+        "sqlite" => OutputFormat::Sqlite,
+        "duckdb" if cfg!(feature = "duckdb") => OutputFormat::Duckdb,
+        "duckdb" => {
+            return Err(anyhow!(
+                "Built without duckdb, rebuild with --features duckdb"
+            ))
+        }
+        format => return Err(anyhow!("Invalid format: {}", format)),
+    };
+    let csv_options = opts.csv_delimiter.is_some() || opts.csv_quote.is_some() || opts.no_header;
+    if csv_options && !matches!(format, OutputFormat::Csv(_)) {
+        return Err(anyhow!("Csv options need --format csv"));
+    }
+    let parquet_options = opts.compression.is_some()
+        || opts.row_group_size.is_some()
+        || opts.dictionary
+        || opts.no_statistics;
+    if parquet_options && !matches!(format, OutputFormat::Parquet(_)) {
+        return Err(anyhow!("Parquet options need --format parquet"));
+    }
+    Ok(format)
+}
+
+fn get_compression(compression: Option<&str>) -> Result<CompressionOptions> {
+    let compression = match compression {
+        Some(compression) => compression,
+        None => return Ok(CompressionOptions::Snappy),
+    };
+    let invalid = || {
+        anyhow!(
+            "Invalid compression: {}, expected snappy, zstd[:level], lz4, gzip[:level] or none",
+            compression
+        )
+    };
+    match compression.split_once(':') {
+        None => match compression {
+            "snappy" => Ok(CompressionOptions::Snappy),
+            "zstd" => Ok(CompressionOptions::Zstd(None)),
+            "lz4" => Ok(CompressionOptions::Lz4Raw),
+            "gzip" => Ok(CompressionOptions::Gzip(None)),
+            "none" => Ok(CompressionOptions::Uncompressed),
+            _ => Err(invalid()),
+        },
+        Some(("zstd", level)) => {
+            let level = level.parse().map_err(|_| invalid())?;
+            let level =
+                ZstdLevel::try_new(level).map_err(|_| anyhow!("Zstd level must be 1 to 22"))?;
+            Ok(CompressionOptions::Zstd(Some(level)))
+        }
+        Some(("gzip", level)) => {
+            let level = level.parse().map_err(|_| invalid())?;
+            let level =
+                GzipLevel::try_new(level).map_err(|_| anyhow!("Gzip level must be 0 to 10"))?;
+            Ok(CompressionOptions::Gzip(Some(level)))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn get_csv_char(value: Option<&str>, default: u8) -> Result<u8> {
+    match value {
+        None => Ok(default),
+        Some("tab" | "\\t") => Ok(b'\t'),
+        Some(value) if value.len() == 1 && value != "\n" => Ok(value.as_bytes()[0]),
+        Some(value) => Err(anyhow!(
+            "Invalid csv character: {}, expected a single ascii character",
+            value
+        )),
+    }
+}
+
 fn get_dataset(dataset: Option<String>) -> Result<Dataset> {
     match dataset {
         Some(dataset) => match dataset.as_str() {
@@ -223,23 +985,62 @@ fn get_dataset(dataset: Option<String>) -> Result<Dataset> {
 fn get_options(
     options: Option<Vec<String>>,
     dataset: Dataset,
+    abi: Option<&Abi>,
 ) -> Result<HashMap<String, Vec<String>>, anyhow::Error> {
     match options {
         Some(options) => {
-            if dataset == Dataset::Blocks || options.is_empty() {
-                return Ok(HashMap::new());
-            }
             let verified_options = get_verified_options(dataset).unwrap();
             let mut options_map: HashMap<String, Vec<String>> = HashMap::new();
+            let mut seen: HashSet<(String, String)> = HashSet::new();
             for option in options {
-                let option_value = option.split(":").collect::<Vec<&str>>();
-                if verified_options.contains(&option_value[0].to_string()) {
-                    options_map.insert(
-                        option_value[0].to_string(),
-                        vec![option_value[1].to_string()], //add mult append
-                    );
-                } else {
-                    return Err(anyhow!("Invalid option"));
+                if option.trim().is_empty() {
+                    continue;
+                }
+                let (option_key, values) = option
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("Invalid option: {}, expected `key:value`", option))?;
+                // event and function names are looked up in the ABI
+                let (key, by_name) = match option_key {
+                    "event" => ("topic0", true),
+                    "function" => ("sighash", true),
+                    key => (key, false),
+                };
+                if !verified_options.contains(&key.to_string()) {
+                    return Err(anyhow!(
+                        "Invalid option: {} is not a filter for {:?}",
+                        option_key,
+                        dataset
+                    ));
+                }
+                // repeated keys, comma separated lists and @files all merge into one list
+                let option_values = options_map.entry(key.to_string()).or_default();
+                for value in split_top_level(values).into_iter().map(str::trim) {
+                    if value.is_empty() {
+                        return Err(anyhow!("Missing value for option: {}", option_key));
+                    }
+                    let values = match value.strip_prefix('@') {
+                        Some(path) => load_option_values(key, path)?,
+                        None if by_name && !value.contains('(') => {
+                            let abi = abi.ok_or_else(|| {
+                                anyhow!(
+                                    "{}:{} needs an ABI, pass one with --abi",
+                                    option_key,
+                                    value
+                                )
+                            })?;
+                            match option_key {
+                                "event" => abi.events(value)?.to_vec(),
+                                _ => abi.functions(value)?.to_vec(),
+                            }
+                        }
+                        None => vec![value.to_owned()],
+                    };
+                    for value in values {
+                        let value = verify_option_value(key, &resolve_signature(key, &value)?)?;
+                        if seen.insert((key.to_string(), value.clone())) {
+                            option_values.push(value);
+                        }
+                    }
                 }
             }
             Ok(options_map)
@@ -248,9 +1049,40 @@ fn get_options(
     }
 }
 
+// signatures are hashed locally, the archive only knows topic0 and sighash values
+fn resolve_signature(key: &str, value: &str) -> Result<String> {
+    match key {
+        "topic0" if value.contains('(') => signature_hash(value, 32),
+        "sighash" if value.contains('(') => signature_hash(value, 4),
+        _ => Ok(value.to_owned()),
+    }
+}
+
+fn verify_option_value(key: &str, value: &str) -> Result<String> {
+    let bytes = match key {
+        "from" | "to" | "address" => 20,
+        "sighash" => 4,
+        "topic0" | "topic1" | "topic2" | "topic3" => 32,
+        _ => return Ok(value.to_owned()),
+    };
+    let hex = value
+        .strip_prefix("0x")
+        .ok_or_else(|| anyhow!("Invalid value for {}: {} must start with 0x", key, value))?;
+    if hex.len() != bytes * 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!(
+            "Invalid value for {}: {} must be {} hex encoded bytes",
+            key,
+            value,
+            bytes
+        ));
+    }
+    // the archive matches on lowercase hex
+    Ok(value.to_lowercase())
+}
+
 fn get_verified_options(dataset: Dataset) -> Option<Vec<String>> {
     match dataset {
-        Dataset::Blocks => Some(vec!["".to_owned()]),
+        Dataset::Blocks => Some(vec![]),
         Dataset::Transactions => Some(vec![
             "from".to_string(),
             "to".to_string(),
@@ -263,44 +1095,57 @@ fn get_verified_options(dataset: Dataset) -> Option<Vec<String>> {
             "topic2".to_string(),
             "topic3".to_string(),
         ]),
+        Dataset::Traces | Dataset::StateDiffs => Some(vec![]),
         //_ => None,
     }
 }
 
+/// Options of a `dataset` query over `range`, the base the tests add their flags to
+#[cfg(test)]
+pub fn test_opts(dataset: &str, range: &str) -> Opts {
+    Opts {
+        dataset: Some(dataset.to_owned()),
+        range: Some(range.to_owned()),
+        ..Default::default()
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
-    use super::{Config, Dataset, Range};
+    use super::{
+        test_opts, Config, CsvOptions, Dataset, Destination, Encoding, OutputFormat,
+        ParquetOptions, Partition, Quantity, Range, WriteMode,
+    };
     use crate::cli::opts::Opts;
     use anyhow::Result;
+    use polars::export::arrow::io::parquet::write::{CompressionOptions, ZstdLevel};
 
     #[test]
     fn test_blocks() -> Result<()> {
         let opts: Config = Opts {
-            dataset: Some("blocks".to_owned()),
-            range: Some("1:10".to_owned()),
             fields: Some(vec!["timestamp".to_owned()]),
             options: Some(vec!["".to_owned()]),
+            ..test_opts("blocks", "1:10")
         }
         .try_into()?;
         assert_eq!(opts.dataset, Dataset::Blocks);
         assert_eq!(opts.range, Range { start: 1, end: 10 });
         assert_eq!(opts.fields, vec!["timestamp".to_owned()]);
 
-        return Ok(());
+        Ok(())
     }
     //TODO add fields vs datasets
     #[test]
     fn test_block_fields() -> Result<()> {
         let opts: Config = Opts {
-            dataset: Some("blocks".to_owned()),
-            range: Some("1:10000".to_owned()),
             fields: Some(vec![
                 "timestamp".to_owned(),
                 "miner".to_owned(),
                 "logsBloom".to_owned(),
             ]),
             options: Some(vec!["".to_owned()]),
+            ..test_opts("blocks", "1:10000")
         }
         .try_into()?;
         assert_eq!(opts.dataset, Dataset::Blocks);
@@ -320,15 +1165,14 @@ mod tests {
             ]
         );
 
-        return Ok(());
+        Ok(())
     }
     #[test]
     fn test_transaction_fields() -> Result<()> {
         let opts: Config = Opts {
-            dataset: Some("transactions".to_owned()),
-            range: Some("1:10000".to_owned()),
             fields: Some(vec!["id".to_owned(), "from".to_owned(), "to".to_owned()]),
             options: Some(vec!["".to_owned()]),
+            ..test_opts("transactions", "1:10000")
         }
         .try_into()?;
         print!("{:?}", opts);
@@ -344,7 +1188,532 @@ mod tests {
             opts.fields,
             vec!["id".to_owned(), "from".to_owned(), "to".to_owned()]
         );
-        return Ok(());
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_fields() -> Result<()> {
+        let opts: Config = Opts {
+            fields: Some(vec!["hash".to_owned(), "logIndex".to_owned()]),
+            ..test_opts("logs", "1:10")
+        }
+        .try_into()?;
+        // the old name of the transaction hash still works
+        assert_eq!(
+            opts.fields,
+            vec!["transactionHash".to_owned(), "logIndex".to_owned()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_headers() -> Result<()> {
+        let opts: Config = Opts {
+            bearer_token: Some("secret".to_owned()),
+            headers: vec!["X-Api-Key: abc:def".to_owned()],
+            ..test_opts("blocks", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(
+            opts.http.headers,
+            vec![("X-Api-Key".to_owned(), "abc:def".to_owned())]
+        );
+        assert!(!format!("{:?}", opts.http).contains("secret"));
+
+        let invalid: Result<Config> = Opts {
+            headers: vec!["X-Api-Key".to_owned()],
+            ..test_opts("blocks", "1:10")
+        }
+        .try_into();
+        assert!(invalid.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_options() -> Result<()> {
+        let a = "0x".to_owned() + &"a".repeat(40);
+        let b = "0x".to_owned() + &"B".repeat(40);
+        let opts: Config = Opts {
+            options: Some(vec![
+                format!("to:{}", a),
+                format!("to:{},{}", b, a),
+                "sighash:0xa9059cbb".to_owned(),
+            ]),
+            ..test_opts("transactions", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(opts.options["to"], vec![a, b.to_lowercase()]);
+        assert_eq!(opts.options["sighash"], vec!["0xa9059cbb".to_owned()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_options_from_file() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let a = "0x".to_owned() + &"a".repeat(40);
+        let b = "0x".to_owned() + &"b".repeat(40);
+        let txt = dir.join("addresses.txt");
+        std::fs::write(&txt, format!("# watchlist\n{}\n\n{}\n", a, b))?;
+        let csv = dir.join("addresses.csv");
+        std::fs::write(&csv, format!("name,from\nfoo,{}\nbar,{}\n", b, a))?;
+
+        let opts: Config = Opts {
+            options: Some(vec![
+                format!("to:@{}", txt.display()),
+                format!("from:@{}", csv.display()),
+            ]),
+            ..test_opts("transactions", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(opts.options["to"], vec![a.clone(), b.clone()]);
+        assert_eq!(opts.options["from"], vec![b, a]);
+
+        let invalid: Result<Config> = Opts {
+            options: Some(vec![format!("from:@{}#name", csv.display())]),
+            ..test_opts("transactions", "1:10")
+        }
+        .try_into();
+        assert!(invalid.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_options() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let abi = dir.join("erc20.json");
+        std::fs::write(
+            &abi,
+            r#"[
+                {"type": "event", "name": "Transfer", "inputs": [
+                    {"name": "from", "type": "address", "indexed": true},
+                    {"name": "to", "type": "address", "indexed": true},
+                    {"name": "value", "type": "uint256", "indexed": false}
+                ]},
+                {"type": "function", "name": "transfer", "inputs": [
+                    {"name": "to", "type": "address"},
+                    {"name": "value", "type": "uint256"}
+                ]}
+            ]"#,
+        )?;
+        let transfer_topic = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+        let opts: Config = Opts {
+            options: Some(vec![
+                "sighash:transfer(address,uint256),0x095ea7b3".to_owned(),
+                "function:transfer".to_owned(),
+            ]),
+            abi: Some(abi.display().to_string()),
+            ..test_opts("transactions", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(
+            opts.options["sighash"],
+            vec!["0xa9059cbb".to_owned(), "0x095ea7b3".to_owned()]
+        );
+
+        let opts: Config = Opts {
+            options: Some(vec![
+                "topic0:Transfer(address indexed from, address indexed to, uint256 value)"
+                    .to_owned(),
+                "event:Transfer".to_owned(),
+            ]),
+            abi: Some(abi.display().to_string()),
+            ..test_opts("logs", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(opts.options["topic0"], vec![transfer_topic.to_owned()]);
+
+        let no_abi: Result<Config> = Opts {
+            options: Some(vec!["event:Transfer".to_owned()]),
+            ..test_opts("logs", "1:10")
+        }
+        .try_into();
+        assert!(no_abi.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_relations() -> Result<()> {
+        let opts: Config = Opts {
+            include_logs: true,
+            include_traces: true,
+            ..test_opts("transactions", "1:10")
+        }
+        .try_into()?;
+        let relations: Vec<Dataset> = opts.relations.iter().map(|r| r.dataset).collect();
+        assert_eq!(relations, vec![Dataset::Logs, Dataset::Traces]);
+        assert_eq!(opts.relations[0].fields[0], "blockNumber");
+
+        let invalid: Result<Config> = Opts {
+            include_logs: true,
+            ..test_opts("logs", "1:10")
+        }
+        .try_into();
+        assert!(invalid.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter() -> Result<()> {
+        let config: Config = Opts {
+            filter: Some("value > 1e18".to_owned()),
+            ..test_opts("transactions", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(config.filter.unwrap().columns(), vec!["value"]);
+
+        let unknown_column: Result<Config> = Opts {
+            filter: Some("gasUsed > 10000000".to_owned()),
+            ..test_opts("transactions", "1:10")
+        }
+        .try_into();
+        assert!(unknown_column.is_err());
+
+        // the derived date column can be filtered on once it is written
+        let date_filter = |date_column: bool| -> Result<Config> {
+            Opts {
+                filter: Some("date == '2023-10-01'".to_owned()),
+                date_column,
+                ..test_opts("transactions", "1:10")
+            }
+            .try_into()
+        };
+        assert_eq!(date_filter(true)?.filter.unwrap().columns(), vec!["date"]);
+        assert!(date_filter(false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_format() -> Result<()> {
+        let config: Config = Opts {
+            format: Some("csv".to_owned()),
+            csv_delimiter: Some("tab".to_owned()),
+            no_header: true,
+            ..test_opts("blocks", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(
+            config.output.format,
+            OutputFormat::Csv(CsvOptions {
+                delimiter: b'\t',
+                quote: b'"',
+                header: false,
+            })
+        );
+
+        for (format, delimiter) in [("csv", ";;"), ("parquet", ";"), ("xlsx", ",")] {
+            let invalid: Result<Config> = Opts {
+                format: Some(format.to_owned()),
+                csv_delimiter: Some(delimiter.to_owned()),
+                ..test_opts("blocks", "1:10")
+            }
+            .try_into();
+            assert!(invalid.is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parquet_options() -> Result<()> {
+        let config: Config = Opts {
+            compression: Some("zstd:19".to_owned()),
+            row_group_size: Some(10000),
+            no_statistics: true,
+            ..test_opts("blocks", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(
+            config.output.format,
+            OutputFormat::Parquet(ParquetOptions {
+                compression: CompressionOptions::Zstd(Some(ZstdLevel::try_new(19)?)),
+                row_group_size: Some(10000),
+                dictionary: false,
+                statistics: false,
+            })
+        );
+
+        for (format, compression) in [
+            ("parquet", "zstd:23"),
+            ("parquet", "gzip:x"),
+            ("parquet", "brotli"),
+            ("csv", "snappy"),
+        ] {
+            let invalid: Result<Config> = Opts {
+                format: Some(format.to_owned()),
+                compression: Some(compression.to_owned()),
+                ..test_opts("blocks", "1:10")
+            }
+            .try_into();
+            assert!(invalid.is_err());
+        }
+
+        for (format, valid) in [("parquet", true), ("arrow-stream", true), ("csv", false)] {
+            let config: Result<Config> = Opts {
+                format: Some(format.to_owned()),
+                binary: true,
+                ..test_opts("blocks", "1:10")
+            }
+            .try_into();
+            assert_eq!(config.is_ok(), valid);
+            if let Ok(config) = config {
+                assert_eq!(config.output.columns.encoding, Encoding::Binary);
+            }
+        }
+        // hex is the default, and can be given explicitly in any format
+        let config: Config = Opts {
+            format: Some("csv".to_owned()),
+            hex: true,
+            ..test_opts("blocks", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(config.output.columns.encoding, Encoding::Hex);
+        Ok(())
+    }
+
+    #[test]
+    fn test_u256_format() -> Result<()> {
+        for (u256, format, quantity) in [
+            ("f64", "csv", Some(Quantity::Float)),
+            ("decimal:18", "parquet", Some(Quantity::Decimal(18))),
+            ("split", "arrow", Some(Quantity::Split)),
+            ("decimal", "csv", None),
+            ("decimal:39", "parquet", None),
+            ("u64", "parquet", None),
+        ] {
+            let config: Result<Config> = Opts {
+                format: Some(format.to_owned()),
+                u256: Some(u256.to_owned()),
+                ..test_opts("transactions", "1:10")
+            }
+            .try_into();
+            assert_eq!(
+                config.ok().map(|config| config.output.columns.quantity),
+                quantity
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stdout_output() -> Result<()> {
+        let config: Config = Opts {
+            format: Some("ndjson".to_owned()),
+            output: Some("-".to_owned()),
+            nested: true,
+            ..test_opts("blocks", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(config.output.destination, Destination::Stdout);
+        assert!(config.output.nested);
+        assert_eq!(config.output.nested_transactions, None);
+
+        // nested blocks carry their transactions, even to stdout
+        let config: Config = Opts {
+            format: Some("ndjson".to_owned()),
+            output: Some("-".to_owned()),
+            nested: true,
+            include_transaction: true,
+            ..test_opts("blocks", "1:10")
+        }
+        .try_into()?;
+        let tx_fields = config.output.nested_transactions.unwrap();
+        assert!(tx_fields.contains(&"hash".to_owned()));
+        assert!(!tx_fields.contains(&"blockNumber".to_owned()));
+
+        let invalid = [
+            ("blocks", "parquet", false),
+            ("blocks", "arrow", false),
+            ("transactions", "json", true),
+            ("blocks", "csv", true),
+        ];
+        for (dataset, format, nested) in invalid {
+            let config: Result<Config> = Opts {
+                format: Some(format.to_owned()),
+                output: Some("-".to_owned()),
+                nested,
+                ..test_opts(dataset, "1:10")
+            }
+            .try_into();
+            assert!(config.is_err());
+        }
+
+        let resume: Result<Config> = Opts {
+            format: Some("csv".to_owned()),
+            output: Some("-".to_owned()),
+            resume: true,
+            ..test_opts("blocks", "1:10")
+        }
+        .try_into();
+        assert!(resume.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_output() -> Result<()> {
+        let config: Config = Opts {
+            format: Some("sqlite".to_owned()),
+            output: Some("chain.db".to_owned()),
+            include_logs: true,
+            ..test_opts("transactions", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(
+            config.output.destination,
+            Destination::Database("chain.db".to_owned())
+        );
+
+        let fields = [None, Some(vec!["from".to_owned(), "to".to_owned()])];
+        for (output, fields) in [None, Some("chain.db")].into_iter().zip(fields) {
+            let invalid: Result<Config> = Opts {
+                fields,
+                format: Some("sqlite".to_owned()),
+                output: output.map(String::from),
+                ..test_opts("transactions", "1:10")
+            }
+            .try_into();
+            assert!(invalid.is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_files() -> Result<()> {
+        let config: Config = Opts {
+            output_dir: Some("out".to_owned()),
+            ..test_opts("transactions", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(
+            config.output.destination,
+            Destination::Directory("out".to_owned())
+        );
+        assert_eq!(
+            config
+                .output
+                .file_name(Dataset::Transactions, 18000000, 18009999),
+            "ethereum__transactions__18000000_to_18009999.parquet"
+        );
+
+        let config: Config = Opts {
+            archive: Some("https://v2.archive.subsquid.io/network/base-sepolia/".to_owned()),
+            filename_template: Some("{network}-{start}".to_owned()),
+            format: Some("csv".to_owned()),
+            ..test_opts("logs", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(
+            config.output.file_name(Dataset::Logs, 5, 9),
+            "base-sepolia-5.csv"
+        );
+
+        for (template, include_transaction) in [
+            ("{dataset}", false),
+            ("{start}_{chain}", false),
+            ("{start}", true),
+        ] {
+            let invalid: Result<Config> = Opts {
+                filename_template: Some(template.to_owned()),
+                include_transaction,
+                ..test_opts("logs", "1:10")
+            }
+            .try_into();
+            assert!(invalid.is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_mode() -> Result<()> {
+        let config: Config = Opts {
+            mode: Some("append".to_owned()),
+            ..test_opts("blocks", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(config.output.mode, WriteMode::Append);
+        let output = &config.output;
+        assert_eq!(
+            output.file_range(Dataset::Blocks, "ethereum__blocks__100_to_199.parquet"),
+            Some(Range {
+                start: 100,
+                end: 199
+            })
+        );
+        for name in [
+            "ethereum__blocks__100_to_199.csv",
+            "ethereum__logs__100_to_199.parquet",
+            "ethereum__blocks__199_to_100.parquet",
+            "ethereum__blocks__100_to_199_old.parquet",
+        ] {
+            assert_eq!(output.file_range(Dataset::Blocks, name), None);
+        }
+
+        for (mode, template, output) in [
+            ("merge", None, None),
+            ("append", Some("{network}-{start}"), None),
+            ("skip-existing", None, Some("-")),
+        ] {
+            let invalid: Result<Config> = Opts {
+                mode: Some(mode.to_owned()),
+                filename_template: template.map(str::to_owned),
+                format: Some("csv".to_owned()),
+                output: output.map(str::to_owned),
+                ..test_opts("blocks", "1:10")
+            }
+            .try_into();
+            assert!(invalid.is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_partition() -> Result<()> {
+        let config: Config = Opts {
+            partition_by: Some("block_bucket:100000".to_owned()),
+            include_logs: true,
+            ..test_opts("transactions", "1:10")
+        }
+        .try_into()?;
+        assert_eq!(
+            config.output.partition,
+            Some(Partition::BlockBucket(100000))
+        );
+        assert_eq!(
+            config.output.file_name(Dataset::Logs, 100000, 199999),
+            "part-100000_to_199999.parquet"
+        );
+
+        for (partition, output) in [("day", None), ("block_bucket:0", None), ("date", Some("-"))] {
+            let invalid: Result<Config> = Opts {
+                partition_by: Some(partition.to_owned()),
+                format: Some("csv".to_owned()),
+                output: output.map(String::from),
+                ..test_opts("transactions", "1:10")
+            }
+            .try_into();
+            assert!(invalid.is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let invalid = [
+            "to:",
+            "to",
+            "to:0x1234",
+            "sighash:a9059cbb",
+            "topic0:0xa9059cbb",
+        ];
+        for option in invalid {
+            let config: Result<Config> = Opts {
+                options: Some(vec![option.to_owned()]),
+                ..test_opts("transactions", "1:10")
+            }
+            .try_into();
+            assert!(config.is_err(), "{} should be rejected", option);
+        }
     }
 }
 
@@ -1,2 +1,4 @@
 pub mod config;
+pub mod option_file;
 pub mod opts;
+pub mod signature;
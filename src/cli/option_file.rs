@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Context, Result};
+use polars::prelude::{CsvReader, DataFrame, DataType, ParquetReader, SerReader};
+use std::fs::{self, File};
+use std::path::Path;
+
+/// Loads filter values from `path[#column]`.
+/// Text files hold one value per line, csv and parquet files are read from
+/// `column`, the column named after the option or else the first column.
+pub fn load_option_values(key: &str, spec: &str) -> Result<Vec<String>> {
+    let (path, column) = match spec.rsplit_once('#') {
+        Some((path, column)) => (path, Some(column)),
+        None => (spec, None),
+    };
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    let values = match extension.as_deref() {
+        Some("csv") => {
+            let has_header = csv_has_header(path)?;
+            let df = CsvReader::from_path(path)
+                .and_then(|reader| reader.has_header(has_header).finish())
+                .with_context(|| format!("Failed to read {}", path))?;
+            column_values(&df, key, column, path)?
+        }
+        Some("parquet") => {
+            let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+            let df = ParquetReader::new(file)
+                .finish()
+                .with_context(|| format!("Failed to read {}", path))?;
+            column_values(&df, key, column, path)?
+        }
+        _ => fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect(),
+    };
+
+    if values.is_empty() {
+        return Err(anyhow!("No values for option {} in {}", key, path));
+    }
+    Ok(values)
+}
+
+// address lists come with and without a header row, a header never starts with 0x
+fn csv_has_header(path: &str) -> Result<bool> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    let first_cell = content
+        .lines()
+        .next()
+        .and_then(|line| line.split(',').next())
+        .unwrap_or("")
+        .trim()
+        .trim_matches('"');
+    Ok(!first_cell.starts_with("0x"))
+}
+
+fn column_values(
+    df: &DataFrame,
+    key: &str,
+    column: Option<&str>,
+    path: &str,
+) -> Result<Vec<String>> {
+    let series = match column {
+        Some(column) => df.column(column)?,
+        None => match df.column(key) {
+            Ok(series) => series,
+            Err(_) => df
+                .get_columns()
+                .first()
+                .ok_or_else(|| anyhow!("No columns in {}", path))?,
+        },
+    };
+    let series = series.cast(&DataType::Utf8)?;
+    Ok(series
+        .utf8()?
+        .into_iter()
+        .flatten()
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+        .collect())
+}
@@ -1,8 +1,10 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
-#[derive(Parser, Debug)]
-#[clap()]
+#[derive(Parser, Debug, Default)]
+#[clap(args_conflicts_with_subcommands = true)]
 pub struct Opts {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
     //#[clap(short = 'a', long = "args")]
     //pub args: Vec<String>,
     #[clap(short = 'd', long = "dataset")]
@@ -13,4 +15,123 @@ pub struct Opts {
     pub fields: Option<Vec<String>>,
     #[clap(short = 'o', long = "options", num_args(0..))]
     pub options: Option<Vec<String>>,
+    /// ABI json used to resolve `event:<name>` and `function:<name>` options
+    #[clap(long = "abi")]
+    pub abi: Option<String>,
+    /// Keep only rows matching an expression, e.g. `value > 1e18 && status == 1`
+    #[clap(long = "where")]
+    pub filter: Option<String>,
+    /// Output format: parquet (default), csv, json, ndjson, arrow, arrow-stream, sqlite or duckdb
+    #[clap(long = "format")]
+    pub format: Option<String>,
+    /// Where to write, `-` streams csv, json and arrow-stream rows to stdout,
+    /// the database file for sqlite and duckdb
+    #[clap(long = "output")]
+    pub output: Option<String>,
+    /// Blocks per output file, files cover spans aligned to multiples of this size
+    #[clap(long = "chunk-size")]
+    pub chunk_size: Option<u64>,
+    /// Directory for output files, the working directory by default
+    #[clap(long = "output-dir")]
+    pub output_dir: Option<String>,
+    /// File name without extension, from `{network}`, `{dataset}`, `{start}` and `{end}`,
+    /// `part-{start}_to_{end}` in partitioned output
+    #[clap(long = "filename-template")]
+    pub filename_template: Option<String>,
+    /// Hive-style directories by block date, month or `block_bucket[:<blocks>]`
+    #[clap(long = "partition-by")]
+    pub partition_by: Option<String>,
+    /// `overwrite` (default) existing files, `append` the blocks after the last file or
+    /// `skip-existing` blocks, fetching only what the output directory is missing
+    #[clap(long = "mode")]
+    pub mode: Option<String>,
+    /// Continue an interrupted run after the last block of its checkpoint
+    #[clap(long = "resume")]
+    pub resume: bool,
+    /// Write blocks as the archive returns them, the header nested with the transactions of
+    /// `--include-transaction` (json only)
+    #[clap(long = "nested")]
+    pub nested: bool,
+    /// Csv field delimiter, a single character or `tab`
+    #[clap(long = "csv-delimiter")]
+    pub csv_delimiter: Option<String>,
+    /// Csv quote character used for values containing the delimiter or quotes
+    #[clap(long = "csv-quote")]
+    pub csv_quote: Option<String>,
+    /// Write csv files without a header row
+    #[clap(long = "no-header")]
+    pub no_header: bool,
+    /// Write hashes, addresses and input as 0x prefixed hex text (default)
+    #[clap(long = "hex", conflicts_with = "binary")]
+    pub hex: bool,
+    /// Write hashes, addresses and input as binary columns (parquet and arrow only)
+    #[clap(long = "binary")]
+    pub binary: bool,
+    /// Quantities such as value and gas prices as decimal `string` (default), `f64`,
+    /// `decimal[:scale]` or `split` high and low halves (the last two parquet and arrow only).
+    /// DuckDB stores decimal strings as HUGEINT and fails on values past 2^127
+    #[clap(long = "u256")]
+    pub u256: Option<String>,
+    /// Keep block timestamps as unix seconds instead of UTC datetimes in parquet and arrow
+    #[clap(long = "raw-timestamps")]
+    pub raw_timestamps: bool,
+    /// Add a `date` column with the day of the block to every row
+    #[clap(long = "date-column")]
+    pub date_column: bool,
+    /// Parquet compression: snappy (default), zstd[:level], lz4, gzip[:level] or none
+    #[clap(long = "compression")]
+    pub compression: Option<String>,
+    /// Rows per parquet row group, a whole chunk by default
+    #[clap(long = "row-group-size")]
+    pub row_group_size: Option<usize>,
+    /// Dictionary encode parquet text columns such as addresses
+    #[clap(long = "dictionary")]
+    pub dictionary: bool,
+    /// Write parquet pages without min/max statistics
+    #[clap(long = "no-statistics")]
+    pub no_statistics: bool,
+    /// Also fetch the logs of matched transactions
+    #[clap(long = "include-logs")]
+    pub include_logs: bool,
+    /// Also fetch the transaction of matched logs, or the transactions of blocks
+    #[clap(long = "include-transaction")]
+    pub include_transaction: bool,
+    /// Also fetch the traces of matched transactions or logs
+    #[clap(long = "include-traces")]
+    pub include_traces: bool,
+    /// Also fetch the state diffs of matched transactions
+    #[clap(long = "include-state-diffs")]
+    pub include_state_diffs: bool,
+    /// Archive endpoint, e.g. a self-hosted archive
+    #[clap(short = 'a', long = "archive")]
+    pub archive: Option<String>,
+    /// Seconds to wait for a connection to be established
+    #[clap(long = "connect-timeout")]
+    pub connect_timeout: Option<u64>,
+    /// Seconds to wait for the next piece of a response
+    #[clap(long = "read-timeout")]
+    pub read_timeout: Option<u64>,
+    /// HTTP(S) proxy for all requests
+    #[clap(long = "proxy")]
+    pub proxy: Option<String>,
+    /// Token sent as `Authorization: Bearer <token>`
+    #[clap(long = "bearer-token")]
+    pub bearer_token: Option<String>,
+    /// Extra request header as `Name: value`, can be repeated
+    #[clap(short = 'H', long = "header")]
+    pub headers: Vec<String>,
+    /// Do not ask the archive for gzip/brotli compressed responses
+    #[clap(long = "no-compression")]
+    pub no_compression: bool,
+    #[clap(long = "user-agent")]
+    pub user_agent: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print the run metadata of output files and databases
+    Inspect {
+        #[clap(required = true)]
+        files: Vec<String>,
+    },
 }
@@ -0,0 +1,211 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use tiny_keccak::{Hasher, Keccak};
+
+/// Hex encoded keccak256 of `data`, truncated to `bytes`
+pub fn keccak_hex(data: &str, bytes: usize) -> String {
+    let mut hasher = Keccak::v256();
+    let mut output = [0u8; 32];
+    hasher.update(data.as_bytes());
+    hasher.finalize(&mut output);
+    let hex: String = output[..bytes]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("0x{}", hex)
+}
+
+/// Hash of a signature such as `Transfer(address indexed from, address to, uint256)`,
+/// 32 bytes for event topics and 4 bytes for function sighashes
+pub fn signature_hash(signature: &str, bytes: usize) -> Result<String> {
+    Ok(keccak_hex(&canonical_signature(signature)?, bytes))
+}
+
+/// Strips parameter names, `indexed` and whitespace and expands type aliases,
+/// e.g. `transfer(address to, uint amount)` becomes `transfer(address,uint256)`
+pub fn canonical_signature(signature: &str) -> Result<String> {
+    let invalid = || anyhow!("Invalid signature: {}", signature);
+    let open = signature.find('(').ok_or_else(invalid)?;
+    let name = signature[..open].trim();
+    let params = signature[open..].trim();
+    if name.is_empty() || !params.ends_with(')') {
+        return Err(invalid());
+    }
+    Ok(format!(
+        "{}{}",
+        name,
+        canonical_tuple(params).ok_or_else(invalid)?
+    ))
+}
+
+// `(type name, ...)` with an optional array suffix after the closing parenthesis
+fn canonical_tuple(tuple: &str) -> Option<String> {
+    let close = tuple.rfind(')')?;
+    let inner = tuple.get(1..close)?;
+    let suffix = tuple[close + 1..].trim();
+    let params = split_top_level(inner)
+        .into_iter()
+        .filter(|param| !param.trim().is_empty())
+        .map(|param| canonical_param(param.trim()))
+        .collect::<Option<Vec<_>>>()?;
+    Some(format!("({}){}", params.join(","), suffix))
+}
+
+fn canonical_param(param: &str) -> Option<String> {
+    if param.starts_with('(') {
+        let close = param.rfind(')')?;
+        let end = param[close..]
+            .find(char::is_whitespace)
+            .map_or(param.len(), |i| close + i);
+        return canonical_tuple(&param[..end]);
+    }
+    let param_type = param.split_whitespace().next()?;
+    Some(canonical_type(param_type))
+}
+
+fn canonical_type(param_type: &str) -> String {
+    let (base, suffix) = match param_type.find('[') {
+        Some(i) => param_type.split_at(i),
+        None => (param_type, ""),
+    };
+    let base = match base {
+        "uint" => "uint256",
+        "int" => "int256",
+        "fixed" => "fixed128x18",
+        "ufixed" => "ufixed128x18",
+        "byte" => "bytes1",
+        base => base,
+    };
+    format!("{}{}", base, suffix)
+}
+
+/// Splits on commas outside of parentheses, so lists of signatures can be comma separated
+pub fn split_top_level(values: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, c) in values.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&values[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&values[start..]);
+    parts
+}
+
+/// Event and function signatures by name, read from an ABI json file
+#[derive(Debug, Default)]
+pub struct Abi {
+    events: HashMap<String, Vec<String>>,
+    functions: HashMap<String, Vec<String>>,
+}
+
+impl Abi {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content =
+            fs::read_to_string(path).with_context(|| format!("Failed to read ABI {}", path))?;
+        let json: Value =
+            serde_json::from_str(&content).with_context(|| format!("Invalid ABI {}", path))?;
+        // build artifacts wrap the ABI in an object
+        let entries = match json.get("abi").unwrap_or(&json).as_array() {
+            Some(entries) => entries,
+            None => return Err(anyhow!("Invalid ABI {}: expected a list of entries", path)),
+        };
+
+        let mut abi = Abi::default();
+        for entry in entries {
+            let target = match entry["type"].as_str() {
+                Some("event") => &mut abi.events,
+                Some("function") => &mut abi.functions,
+                _ => continue,
+            };
+            let name = entry["name"]
+                .as_str()
+                .ok_or_else(|| anyhow!("Invalid ABI {}: entry without name", path))?;
+            let inputs = entry["inputs"].as_array().cloned().unwrap_or_default();
+            let types = inputs
+                .iter()
+                .map(abi_type)
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| anyhow!("Invalid ABI {}: bad inputs for {}", path, name))?;
+            target.entry(name.to_owned()).or_default().push(format!(
+                "{}({})",
+                name,
+                types.join(",")
+            ));
+        }
+        Ok(abi)
+    }
+
+    /// Signatures of all events called `name`, overloads included
+    pub fn events(&self, name: &str) -> Result<&[String]> {
+        self.events
+            .get(name)
+            .map(Vec::as_slice)
+            .ok_or_else(|| anyhow!("No event {} in ABI", name))
+    }
+
+    /// Signatures of all functions called `name`, overloads included
+    pub fn functions(&self, name: &str) -> Result<&[String]> {
+        self.functions
+            .get(name)
+            .map(Vec::as_slice)
+            .ok_or_else(|| anyhow!("No function {} in ABI", name))
+    }
+}
+
+fn abi_type(input: &Value) -> Option<String> {
+    let param_type = input["type"].as_str()?;
+    match param_type.strip_prefix("tuple") {
+        Some(suffix) => {
+            let components = input["components"]
+                .as_array()?
+                .iter()
+                .map(abi_type)
+                .collect::<Option<Vec<_>>>()?;
+            Some(format!("({}){}", components.join(","), suffix))
+        }
+        None => Some(canonical_type(param_type)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonical_signature, signature_hash, split_top_level};
+    use anyhow::Result;
+
+    #[test]
+    fn test_signature_hash() -> Result<()> {
+        assert_eq!(
+            signature_hash("Transfer(address,address,uint256)", 32)?,
+            "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+        );
+        assert_eq!(
+            signature_hash("transfer(address to, uint amount)", 4)?,
+            "0xa9059cbb"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical_signature() -> Result<()> {
+        assert_eq!(
+            canonical_signature("Swap(address indexed sender, (uint a, int[] b)[] legs, bytes)")?,
+            "Swap(address,(uint256,int256[])[],bytes)"
+        );
+        assert!(canonical_signature("Transfer").is_err());
+        assert_eq!(
+            split_top_level("transfer(address,uint256),0xa9059cbb"),
+            vec!["transfer(address,uint256)", "0xa9059cbb"]
+        );
+        Ok(())
+    }
+}
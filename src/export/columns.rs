@@ -0,0 +1,303 @@
+use crate::cli::config::{ColumnTypes, Dataset, Encoding, Quantity};
+use crate::export::fields::{hex_bytes, is_quantity, is_timestamp, HexBytes, DATE_FIELD};
+use crate::export::u256::U256;
+use chrono::NaiveDate;
+use polars::export::arrow::array::{
+    Array, MutableArray, MutableBinaryArray, MutableFixedSizeBinaryArray, PrimitiveArray, Utf8Array,
+};
+use polars::export::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use polars::prelude::DataFrame;
+use std::io::Error;
+use std::sync::Arc;
+
+const MAX_DECIMAL: i128 = 10i128.pow(38);
+
+/// Arrow schema and columns of the `dataset` rows in `df`, with hex fields decoded to
+/// binary, quantities converted as `columns` asks and block times as UTC timestamps and dates
+pub fn arrow_columns(
+    df: &mut DataFrame,
+    dataset: Dataset,
+    columns: ColumnTypes,
+) -> Result<(Schema, Vec<Arc<dyn Array>>), Error> {
+    df.rechunk();
+    let mut fields = Vec::new();
+    let mut arrays = Vec::new();
+    for series in df.get_columns() {
+        let name = series.name();
+        let array = series.to_arrow(0);
+        let bytes = hex_bytes(dataset, name).filter(|_| columns.encoding == Encoding::Binary);
+        let converted = match (bytes, columns.quantity) {
+            (Some(bytes), _) => vec![(name.to_owned(), decode_column(name, &*array, bytes)?)],
+            _ if is_timestamp(dataset, name) && !columns.raw_timestamps => {
+                vec![(name.to_owned(), timestamp_column(name, &*array)?)]
+            }
+            _ if name == DATE_FIELD && columns.date => {
+                vec![(name.to_owned(), date_column(name, &*array)?)]
+            }
+            (None, Quantity::Decimal(scale)) if is_quantity(dataset, name) => {
+                vec![(name.to_owned(), decimal_column(name, &*array, scale)?)]
+            }
+            (None, Quantity::Split) if is_quantity(dataset, name) => {
+                let [high, low] = split_column(name, &*array)?;
+                vec![
+                    (format!("{}_high", name), high),
+                    (format!("{}_low", name), low),
+                ]
+            }
+            _ => vec![(name.to_owned(), array)],
+        };
+        for (name, array) in converted {
+            fields.push(Field::new(name, array.data_type().clone(), true));
+            arrays.push(array);
+        }
+    }
+    Ok((Schema::from(fields), arrays))
+}
+
+fn text_column<'a>(name: &str, array: &'a dyn Array) -> Result<&'a Utf8Array<i64>, Error> {
+    array
+        .as_any()
+        .downcast_ref::<Utf8Array<i64>>()
+        .ok_or_else(|| Error::other(format!("{} is not text", name)))
+}
+
+fn timestamp_column(name: &str, array: &dyn Array) -> Result<Arc<dyn Array>, Error> {
+    let seconds = array
+        .as_any()
+        .downcast_ref::<PrimitiveArray<u64>>()
+        .ok_or_else(|| Error::other(format!("{} is not a number", name)))?;
+    let column = PrimitiveArray::<i64>::from_trusted_len_iter(
+        seconds.iter().map(|value| value.map(|value| *value as i64)),
+    )
+    .to(DataType::Timestamp(
+        TimeUnit::Second,
+        Some("UTC".to_owned()),
+    ));
+    Ok(Arc::new(column))
+}
+
+// date32 counts days since the unix epoch
+fn date_column(name: &str, array: &dyn Array) -> Result<Arc<dyn Array>, Error> {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+    let days = text_column(name, array)?
+        .iter()
+        .map(|value| match value {
+            Some(value) => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|date| Some((date - epoch).num_days() as i32))
+                .map_err(|_| Error::other(format!("Invalid {}: {}", name, value))),
+            None => Ok(None),
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(Arc::new(
+        PrimitiveArray::<i32>::from(days).to(DataType::Date32),
+    ))
+}
+
+fn quantity_values(name: &str, array: &dyn Array) -> Result<Vec<Option<U256>>, Error> {
+    text_column(name, array)?
+        .iter()
+        .map(|value| match value {
+            Some(value) => U256::parse(value)
+                .map(Some)
+                .ok_or_else(|| Error::other(format!("Invalid {}: {}", name, value))),
+            None => Ok(None),
+        })
+        .collect()
+}
+
+// decimal128 holds 38 digits, enough for any wei amount below 10^20 ether
+fn decimal_column(name: &str, array: &dyn Array, scale: u8) -> Result<Arc<dyn Array>, Error> {
+    let values = quantity_values(name, array)?
+        .into_iter()
+        .map(|value| match value {
+            Some(value) => value
+                .to_i128()
+                .filter(|value| *value < MAX_DECIMAL)
+                .map(Some)
+                .ok_or_else(|| Error::other(format!("{} {} exceeds decimal128", name, value))),
+            None => Ok(None),
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let column = PrimitiveArray::<i128>::from(values).to(DataType::Decimal(38, scale as usize));
+    Ok(Arc::new(column))
+}
+
+fn split_column(name: &str, array: &dyn Array) -> Result<[Arc<dyn Array>; 2], Error> {
+    let values = quantity_values(name, array)?;
+    let mut high = MutableFixedSizeBinaryArray::with_capacity(16, values.len());
+    let mut low = MutableFixedSizeBinaryArray::with_capacity(16, values.len());
+    for value in values {
+        high.push(value.map(|value| value.high().to_be_bytes()));
+        low.push(value.map(|value| value.low().to_be_bytes()));
+    }
+    Ok([high.as_arc(), low.as_arc()])
+}
+
+// empty strings are values the archive did not send
+fn decode_column(name: &str, array: &dyn Array, bytes: HexBytes) -> Result<Arc<dyn Array>, Error> {
+    let invalid = |value: &str| Error::other(format!("Invalid {}: {}", name, value));
+    let text = text_column(name, array)?;
+    match bytes {
+        HexBytes::Fixed(size) => {
+            let mut column = MutableFixedSizeBinaryArray::with_capacity(size, text.len());
+            for value in text.iter() {
+                match value.filter(|value| !value.is_empty() && *value != "0x") {
+                    Some(value) => {
+                        let decoded = decode_hex(value)
+                            .filter(|decoded| decoded.len() == size)
+                            .ok_or_else(|| invalid(value))?;
+                        column.push(Some(decoded));
+                    }
+                    None => column.push::<&[u8]>(None),
+                }
+            }
+            Ok(column.as_arc())
+        }
+        HexBytes::Variable => {
+            let mut column = MutableBinaryArray::<i64>::with_capacity(text.len());
+            for value in text.iter() {
+                match value.filter(|value| !value.is_empty()) {
+                    Some(value) => {
+                        column.push(Some(decode_hex(value).ok_or_else(|| invalid(value))?))
+                    }
+                    None => column.push::<&[u8]>(None),
+                }
+            }
+            Ok(column.as_arc())
+        }
+    }
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    let digits = value.strip_prefix("0x")?.as_bytes();
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::arrow_columns;
+    use crate::cli::config::{ColumnTypes, Dataset, Encoding, Quantity};
+    use polars::export::arrow::array::{Array, BinaryArray, FixedSizeBinaryArray, PrimitiveArray};
+    use polars::export::arrow::datatypes::{DataType, TimeUnit};
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    #[test]
+    fn test_arrow_columns() {
+        let from = format!("0x{}", "ab".repeat(20));
+        let mut df = DataFrame::new(vec![
+            Series::new("from", &[from.as_str(), from.as_str()]),
+            Series::new("to", &[from.as_str(), ""]),
+            Series::new("input", &["0x", "0x0102"]),
+            Series::new("value", &["0x1", "0x0"]),
+        ])
+        .unwrap();
+
+        let (schema, arrays) =
+            arrow_columns(&mut df, Dataset::Transactions, ColumnTypes::default()).unwrap();
+        assert_eq!(schema.fields[0].data_type, DataType::LargeUtf8);
+        assert_eq!(arrays.len(), 4);
+
+        let binary = ColumnTypes {
+            encoding: Encoding::Binary,
+            ..ColumnTypes::default()
+        };
+        let (schema, arrays) = arrow_columns(&mut df, Dataset::Transactions, binary).unwrap();
+        assert_eq!(schema.fields[0].data_type, DataType::FixedSizeBinary(20));
+        assert_eq!(schema.fields[3].data_type, DataType::LargeUtf8);
+        let to = arrays[1]
+            .as_any()
+            .downcast_ref::<FixedSizeBinaryArray>()
+            .unwrap();
+        assert_eq!(to.value(0), [0xab; 20]);
+        assert!(to.is_null(1));
+        let input = arrays[2]
+            .as_any()
+            .downcast_ref::<BinaryArray<i64>>()
+            .unwrap();
+        assert_eq!(input.value(0), b"");
+        assert_eq!(input.value(1), [1, 2]);
+
+        let mut invalid = DataFrame::new(vec![Series::new("from", &["0xab"])]).unwrap();
+        assert!(arrow_columns(&mut invalid, Dataset::Transactions, binary).is_err());
+    }
+
+    #[test]
+    fn test_quantity_columns() {
+        let mut df = DataFrame::new(vec![Series::new(
+            "value",
+            &[
+                "1000000000000000000",
+                "340282366920938463463374607431768211456",
+            ],
+        )])
+        .unwrap();
+        let split = ColumnTypes {
+            quantity: Quantity::Split,
+            ..ColumnTypes::default()
+        };
+        let (schema, arrays) = arrow_columns(&mut df, Dataset::Transactions, split).unwrap();
+        assert_eq!(schema.fields[0].name, "value_high");
+        assert_eq!(schema.fields[1].name, "value_low");
+        let high = arrays[0]
+            .as_any()
+            .downcast_ref::<FixedSizeBinaryArray>()
+            .unwrap();
+        assert_eq!(high.value(1), 1u128.to_be_bytes());
+
+        // 2^128 wei does not fit decimal128
+        let decimal = ColumnTypes {
+            quantity: Quantity::Decimal(18),
+            ..ColumnTypes::default()
+        };
+        assert!(arrow_columns(&mut df, Dataset::Transactions, decimal).is_err());
+        let mut df = df.head(Some(1));
+        let (schema, arrays) = arrow_columns(&mut df, Dataset::Transactions, decimal).unwrap();
+        assert_eq!(schema.fields[0].data_type, DataType::Decimal(38, 18));
+        let value = arrays[0]
+            .as_any()
+            .downcast_ref::<PrimitiveArray<i128>>()
+            .unwrap();
+        assert_eq!(value.value(0), 1_000_000_000_000_000_000);
+    }
+
+    #[test]
+    fn test_time_columns() {
+        let mut df = DataFrame::new(vec![
+            Series::new("timestamp", &[1690000000u64, 1690070400]),
+            Series::new("date", &["2023-07-22", "2023-07-23"]),
+        ])
+        .unwrap();
+        let dated = ColumnTypes {
+            date: true,
+            ..ColumnTypes::default()
+        };
+        let (schema, arrays) = arrow_columns(&mut df, Dataset::Blocks, dated).unwrap();
+        assert_eq!(
+            schema.fields[0].data_type,
+            DataType::Timestamp(TimeUnit::Second, Some("UTC".to_owned()))
+        );
+        assert_eq!(schema.fields[1].data_type, DataType::Date32);
+        let days = arrays[1]
+            .as_any()
+            .downcast_ref::<PrimitiveArray<i32>>()
+            .unwrap();
+        assert_eq!(days.value(0), 19560);
+
+        let raw = ColumnTypes {
+            raw_timestamps: true,
+            ..ColumnTypes::default()
+        };
+        let (schema, _) = arrow_columns(&mut df, Dataset::Blocks, raw).unwrap();
+        assert_eq!(schema.fields[0].data_type, DataType::UInt64);
+        assert_eq!(schema.fields[1].data_type, DataType::LargeUtf8);
+    }
+}
@@ -0,0 +1,35 @@
+use crate::cli::config::{Dataset, OutputConfig, OutputFormat};
+#[cfg(feature = "duckdb")]
+use crate::export::duckdb::DuckdbWriter;
+use crate::export::sqlite::SqliteWriter;
+use polars::prelude::DataFrame;
+use std::io::Error;
+
+/// Sink keeping a database file open across chunks, with a table per dataset
+pub trait DatabaseWriter {
+    /// Writes the rows of every dataset in a chunk
+    fn write_chunk(&mut self, frames: &[(Dataset, DataFrame)]) -> Result<(), Error>;
+
+    /// Merges the run metadata of a chunk into the `_metadata` rows of `dataset`, see
+    /// `merge_metadata`
+    fn write_metadata(
+        &mut self,
+        dataset: Dataset,
+        metadata: Vec<(String, String)>,
+    ) -> Result<(), Error>;
+}
+
+/// Table of the run metadata of every dataset, which files keep in their own metadata
+pub const METADATA_TABLE: &str = "_metadata";
+
+pub fn open_database(output: &OutputConfig, path: &str) -> Result<Box<dyn DatabaseWriter>, Error> {
+    match &output.format {
+        OutputFormat::Sqlite => Ok(Box::new(SqliteWriter::open(path)?)),
+        #[cfg(feature = "duckdb")]
+        OutputFormat::Duckdb => Ok(Box::new(DuckdbWriter::open(path, output.columns)?)),
+        format => Err(Error::other(format!(
+            "{:?} is not a database format",
+            format
+        ))),
+    }
+}
@@ -0,0 +1,369 @@
+use crate::cli::config::{ColumnTypes, Dataset};
+use crate::export::database::{DatabaseWriter, METADATA_TABLE};
+use crate::export::fields::{is_quantity, is_timestamp, DATE_FIELD};
+use crate::export::metadata::merge_metadata;
+use duckdb::types::{TimeUnit, Value as DuckValue};
+use duckdb::{params, params_from_iter, AccessMode, Config, Connection};
+use polars::prelude::{AnyValue, DataFrame, DataType};
+use std::collections::HashSet;
+use std::io::Error;
+use std::path::Path;
+
+/// Persistent DuckDB database, every chunk is appended to the table of its dataset
+pub struct DuckdbWriter {
+    connection: Connection,
+    tables: HashSet<&'static str>,
+    columns: ColumnTypes,
+}
+
+// columns stored as DuckDB types instead of the types of their frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Conversion {
+    Timestamp,
+    Date,
+    HugeInt,
+}
+
+impl DuckdbWriter {
+    pub fn open(path: &str, columns: ColumnTypes) -> Result<Self, Error> {
+        let connection = Connection::open(path).map_err(Error::other)?;
+        Ok(DuckdbWriter {
+            connection,
+            tables: HashSet::new(),
+            columns,
+        })
+    }
+
+    // a prepared insert instead of an appender, whose flush drops its errors
+    fn insert(&self, dataset: Dataset, df: &DataFrame) -> Result<(), Error> {
+        let conversions: Vec<_> = df
+            .get_columns()
+            .iter()
+            .map(|series| conversion(dataset, series.name(), series.dtype(), self.columns))
+            .collect();
+        let placeholders = vec!["?"; df.width()].join(", ");
+        let mut statement = self
+            .connection
+            .prepare(&format!(
+                "INSERT INTO {} VALUES ({})",
+                quote(dataset.name()),
+                placeholders
+            ))
+            .map_err(Error::other)?;
+        for row in 0..df.height() {
+            let values = df
+                .get_columns()
+                .iter()
+                .zip(&conversions)
+                .map(|(series, conversion)| duck_value(series.name(), series.get(row), *conversion))
+                .collect::<Result<Vec<_>, Error>>()?;
+            statement
+                .execute(params_from_iter(values))
+                .map_err(Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+impl DatabaseWriter for DuckdbWriter {
+    fn write_chunk(&mut self, frames: &[(Dataset, DataFrame)]) -> Result<(), Error> {
+        for (dataset, df) in frames {
+            if self.tables.insert(dataset.name()) {
+                create_table(&self.connection, *dataset, df, self.columns)?;
+            }
+        }
+        self.connection
+            .execute_batch("BEGIN TRANSACTION;")
+            .map_err(Error::other)?;
+        for (dataset, df) in frames {
+            if let Err(e) = self.insert(*dataset, df) {
+                let _ = self.connection.execute_batch("ROLLBACK;");
+                return Err(e);
+            }
+        }
+        self.connection
+            .execute_batch("COMMIT;")
+            .map_err(Error::other)
+    }
+
+    fn write_metadata(
+        &mut self,
+        dataset: Dataset,
+        metadata: Vec<(String, String)>,
+    ) -> Result<(), Error> {
+        self.connection
+            .execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {} \
+                 (dataset VARCHAR NOT NULL, key VARCHAR NOT NULL, value VARCHAR);",
+                METADATA_TABLE
+            ))
+            .map_err(Error::other)?;
+        let existing = dataset_metadata(&self.connection, Some(dataset.name()))?;
+        let transaction = self.connection.transaction().map_err(Error::other)?;
+        transaction
+            .execute(
+                &format!("DELETE FROM {} WHERE dataset = ?", METADATA_TABLE),
+                [dataset.name()],
+            )
+            .map_err(Error::other)?;
+        for (key, value) in merge_metadata(&existing, metadata) {
+            transaction
+                .execute(
+                    &format!("INSERT INTO {} VALUES (?, ?, ?)", METADATA_TABLE),
+                    params![dataset.name(), key, value],
+                )
+                .map_err(Error::other)?;
+        }
+        transaction.commit().map_err(Error::other)
+    }
+}
+
+// of one dataset or all of them, in the order they were written
+fn dataset_metadata(
+    connection: &Connection,
+    dataset: Option<&str>,
+) -> Result<Vec<(String, String)>, Error> {
+    let mut statement = connection
+        .prepare(&format!(
+            "SELECT key, value FROM {} WHERE ? IS NULL OR dataset = ? ORDER BY dataset, rowid",
+            METADATA_TABLE
+        ))
+        .map_err(Error::other)?;
+    statement
+        .query_map([dataset, dataset], |row| Ok((row.get(0)?, row.get(1)?)))
+        .and_then(|rows| rows.collect::<duckdb::Result<Vec<_>>>())
+        .map_err(Error::other)
+}
+
+/// Run metadata of every dataset in the DuckDB database at `path`
+pub fn read_metadata(path: &Path) -> Result<Vec<(String, String)>, Error> {
+    let config = Config::default()
+        .access_mode(AccessMode::ReadOnly)
+        .map_err(Error::other)?;
+    let connection = Connection::open_with_flags(path, config).map_err(Error::other)?;
+    dataset_metadata(&connection, None)
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// timestamps unless raw ones are asked for, the date derived from them, and the
+// decimal text of quantities, up to the 2^127 a HUGEINT holds
+fn conversion(
+    dataset: Dataset,
+    name: &str,
+    dtype: &DataType,
+    columns: ColumnTypes,
+) -> Option<Conversion> {
+    if is_timestamp(dataset, name) && !columns.raw_timestamps {
+        Some(Conversion::Timestamp)
+    } else if name == DATE_FIELD && columns.date {
+        Some(Conversion::Date)
+    } else if is_quantity(dataset, name) && dtype == &DataType::Utf8 {
+        Some(Conversion::HugeInt)
+    } else {
+        None
+    }
+}
+
+fn column_type(dtype: &DataType, conversion: Option<Conversion>) -> &'static str {
+    match conversion {
+        Some(Conversion::Timestamp) => "TIMESTAMP",
+        Some(Conversion::Date) => "DATE",
+        Some(Conversion::HugeInt) => "HUGEINT",
+        None => plain_column_type(dtype),
+    }
+}
+
+fn plain_column_type(dtype: &DataType) -> &'static str {
+    match dtype {
+        DataType::Boolean => "BOOLEAN",
+        DataType::UInt8 | DataType::UInt16 | DataType::UInt32 => "UINTEGER",
+        DataType::UInt64 => "UBIGINT",
+        DataType::Int8 | DataType::Int16 | DataType::Int32 => "INTEGER",
+        DataType::Int64 => "BIGINT",
+        DataType::Float32 | DataType::Float64 => "DOUBLE",
+        _ => "VARCHAR",
+    }
+}
+
+// rows are inserted whole, so earlier runs must have used the same fields
+fn create_table(
+    connection: &Connection,
+    dataset: Dataset,
+    df: &DataFrame,
+    column_types: ColumnTypes,
+) -> Result<(), Error> {
+    let table = dataset.name();
+    let columns: Vec<String> = df
+        .get_columns()
+        .iter()
+        .map(|series| {
+            let conversion = conversion(dataset, series.name(), series.dtype(), column_types);
+            format!(
+                "{} {}",
+                quote(series.name()),
+                column_type(series.dtype(), conversion)
+            )
+        })
+        .collect();
+    connection
+        .execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} ({});",
+            quote(table),
+            columns.join(", ")
+        ))
+        .map_err(Error::other)?;
+
+    let mut statement = connection
+        .prepare(
+            "SELECT column_name FROM information_schema.columns \
+             WHERE table_name = ? ORDER BY ordinal_position",
+        )
+        .map_err(Error::other)?;
+    let existing = statement
+        .query_map([table], |row| row.get::<_, String>(0))
+        .and_then(|rows| rows.collect::<duckdb::Result<Vec<String>>>())
+        .map_err(Error::other)?;
+    if existing != df.get_column_names() {
+        return Err(Error::other(format!(
+            "Table {} has columns {:?}, select the same fields to append to it",
+            table, existing
+        )));
+    }
+    Ok(())
+}
+
+// dates are bound as their `%Y-%m-%d` text, which DuckDB casts to the DATE column
+fn duck_value(
+    name: &str,
+    value: AnyValue,
+    conversion: Option<Conversion>,
+) -> Result<DuckValue, Error> {
+    Ok(match value {
+        AnyValue::Null => DuckValue::Null,
+        AnyValue::UInt64(seconds) if conversion == Some(Conversion::Timestamp) => {
+            DuckValue::Timestamp(TimeUnit::Second, seconds as i64)
+        }
+        AnyValue::Utf8(value) if conversion == Some(Conversion::HugeInt) => {
+            DuckValue::HugeInt(value.parse().map_err(|_| {
+                Error::other(format!(
+                    "{} {} does not fit a HUGEINT column, use --u256 f64",
+                    name, value
+                ))
+            })?)
+        }
+        AnyValue::Boolean(value) => DuckValue::Boolean(value),
+        AnyValue::Utf8(value) => DuckValue::Text(value.to_owned()),
+        AnyValue::UInt8(value) => DuckValue::UInt(value as u32),
+        AnyValue::UInt16(value) => DuckValue::UInt(value as u32),
+        AnyValue::UInt32(value) => DuckValue::UInt(value),
+        AnyValue::UInt64(value) => DuckValue::UBigInt(value),
+        AnyValue::Int8(value) => DuckValue::Int(value as i32),
+        AnyValue::Int16(value) => DuckValue::Int(value as i32),
+        AnyValue::Int32(value) => DuckValue::Int(value),
+        AnyValue::Int64(value) => DuckValue::BigInt(value),
+        AnyValue::Float32(value) => DuckValue::Double(value as f64),
+        AnyValue::Float64(value) => DuckValue::Double(value),
+        value => DuckValue::Text(value.to_string()),
+    })
+}
+
+#[cfg(all(test, feature = "duckdb"))]
+mod tests {
+    use super::{read_metadata, DuckdbWriter};
+    use crate::cli::config::{ColumnTypes, Dataset};
+    use crate::export::database::DatabaseWriter;
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    #[test]
+    fn test_append_chunks() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("append.duckdb");
+        let columns = ColumnTypes {
+            date: true,
+            ..Default::default()
+        };
+        let mut writer = DuckdbWriter::open(path.to_str().unwrap(), columns).unwrap();
+        // 2023-10-01 00:00:00 and 00:00:12
+        let chunk = |numbers: &[u64], timestamps: &[u64], fees: &[&str]| {
+            DataFrame::new(vec![
+                Series::new("number", numbers),
+                Series::new("timestamp", timestamps),
+                Series::new("baseFeePerGas", fees),
+                Series::new("date", vec!["2023-10-01"; numbers.len()]),
+            ])
+            .unwrap()
+        };
+        let fees = ["58750003716598352816469", "7"];
+        writer
+            .write_chunk(&[(
+                Dataset::Blocks,
+                chunk(&[1, 2], &[1696118400, 1696118400], &fees),
+            )])
+            .unwrap();
+        writer
+            .write_chunk(&[(Dataset::Blocks, chunk(&[3], &[1696118412], &["3"]))])
+            .unwrap();
+
+        let types: Vec<String> = writer
+            .connection
+            .prepare(
+                "SELECT data_type FROM information_schema.columns \
+                 WHERE table_name = 'blocks' ORDER BY ordinal_position",
+            )
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<duckdb::Result<_>>()
+            .unwrap();
+        assert_eq!(types, vec!["UBIGINT", "TIMESTAMP", "HUGEINT", "DATE"]);
+        let (count, last, fees): (i64, String, String) = writer
+            .connection
+            .query_row(
+                "SELECT count(*), max(timestamp)::VARCHAR, sum(baseFeePerGas)::VARCHAR \
+                 FROM blocks WHERE date = DATE '2023-10-01'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            (count, last.as_str(), fees.as_str()),
+            (3, "2023-10-01 00:00:12", "58750003716598352816479")
+        );
+
+        // quantities past a HUGEINT fail the chunk instead of being dropped
+        let huge = "1".repeat(40);
+        let error = writer
+            .write_chunk(&[(Dataset::Blocks, chunk(&[4], &[1696118424], &[&huge]))])
+            .unwrap_err();
+        assert!(error.to_string().contains("does not fit a HUGEINT"));
+        let count: i64 = writer
+            .connection
+            .query_row("SELECT count(*) FROM blocks", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 3);
+
+        // run metadata sits in a table of its own, widened by every chunk
+        let metadata = |range: &str| {
+            vec![
+                ("dataset".to_owned(), "blocks".to_owned()),
+                ("range".to_owned(), range.to_owned()),
+            ]
+        };
+        writer
+            .write_metadata(Dataset::Blocks, metadata("1:2"))
+            .unwrap();
+        writer
+            .write_metadata(Dataset::Blocks, metadata("3:3"))
+            .unwrap();
+
+        // other fields can not be appended to the table of an earlier run
+        drop(writer);
+        assert_eq!(read_metadata(&path).unwrap(), metadata("1:3"));
+        let other = DataFrame::new(vec![Series::new("hash", &["0xaa"])]).unwrap();
+        let mut writer = DuckdbWriter::open(path.to_str().unwrap(), columns).unwrap();
+        assert!(writer.write_chunk(&[(Dataset::Blocks, other)]).is_err());
+    }
+}
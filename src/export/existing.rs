@@ -0,0 +1,306 @@
+use crate::checkpoint::checkpoint_temp_path;
+use crate::cli::config::{Dataset, Destination, OutputConfig, Range, WriteMode};
+use crate::export::export::TEMP_SUFFIX;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+/// Parts of `range` still to fetch for `datasets`, given the files already in the output
+/// directory. Overwrite fetches the whole range.
+pub fn ranges_to_fetch(
+    range: Range,
+    datasets: &[Dataset],
+    output: &OutputConfig,
+) -> Result<Vec<Range>, Error> {
+    if output.mode == WriteMode::Overwrite {
+        return Ok(vec![range]);
+    }
+    let mut missing = Vec::new();
+    for dataset in datasets {
+        let existing = existing_ranges(*dataset, output)?;
+        missing.extend(missing_ranges(range, existing, output.mode));
+    }
+    Ok(merge_ranges(missing))
+}
+
+/// Block ranges of the `dataset` files in the output directory, read from their names
+pub fn existing_ranges(dataset: Dataset, output: &OutputConfig) -> Result<Vec<Range>, Error> {
+    let mut ranges = Vec::new();
+    for name in file_names(dataset, output)? {
+        if let Some(range) = output.file_range(dataset, &name) {
+            ranges.push(range);
+        }
+    }
+    Ok(ranges)
+}
+
+/// Deletes the temporary files interrupted runs left behind, returning their paths
+pub fn remove_temp_files(
+    datasets: &[Dataset],
+    output: &OutputConfig,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut removed = Vec::new();
+    for dataset in datasets {
+        for dir in dataset_dirs(*dataset, output)? {
+            for name in file_names_in(&dir)? {
+                let file_name = name
+                    .strip_prefix('.')
+                    .and_then(|name| name.strip_suffix(TEMP_SUFFIX));
+                if let Some(file_name) = file_name {
+                    if output.file_range(*dataset, file_name).is_some() {
+                        let path = dir.join(&name);
+                        fs::remove_file(&path)?;
+                        removed.push(path);
+                    }
+                }
+            }
+        }
+        // as does a kill between saving a checkpoint and renaming it
+        if let Some(path) = checkpoint_temp_path(*dataset, output) {
+            if path.exists() {
+                fs::remove_file(&path)?;
+                removed.push(path);
+            }
+        }
+    }
+    Ok(removed)
+}
+
+// directories holding the files of `dataset`, partitioned files sit one level below
+// `dataset=<name>`
+fn dataset_dirs(dataset: Dataset, output: &OutputConfig) -> Result<Vec<PathBuf>, Error> {
+    let folder = match &output.destination {
+        Destination::Directory(dir) => Path::new(dir),
+        _ => return Ok(Vec::new()),
+    };
+    match output.partition {
+        Some(_) => {
+            let dataset_dir = folder.join(format!("dataset={}", dataset.name()));
+            if !dataset_dir.is_dir() {
+                return Ok(Vec::new());
+            }
+            let mut dirs = Vec::new();
+            for entry in fs::read_dir(dataset_dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                }
+            }
+            Ok(dirs)
+        }
+        None if folder.is_dir() => Ok(vec![folder.to_path_buf()]),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn file_names(dataset: Dataset, output: &OutputConfig) -> Result<Vec<String>, Error> {
+    let mut names = Vec::new();
+    for dir in dataset_dirs(dataset, output)? {
+        names.extend(file_names_in(&dir)?);
+    }
+    Ok(names)
+}
+
+fn file_names_in(dir: &Path) -> Result<Vec<String>, Error> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    Ok(names)
+}
+
+/// Parts of `range` not covered by `existing`, only the blocks past the last existing
+/// block when appending
+pub fn missing_ranges(range: Range, existing: Vec<Range>, mode: WriteMode) -> Vec<Range> {
+    let existing = merge_ranges(existing);
+    match mode {
+        WriteMode::Overwrite => vec![range],
+        WriteMode::Append => {
+            let start = existing
+                .last()
+                .map_or(range.start, |last| range.start.max(last.end + 1));
+            if start > range.end {
+                return Vec::new();
+            }
+            vec![Range {
+                start,
+                end: range.end,
+            }]
+        }
+        WriteMode::SkipExisting => {
+            let mut missing = Vec::new();
+            let mut start = range.start;
+            for covered in existing {
+                if covered.end < start {
+                    continue;
+                }
+                if covered.start > range.end {
+                    break;
+                }
+                if covered.start > start {
+                    missing.push(Range {
+                        start,
+                        end: covered.start - 1,
+                    });
+                }
+                start = covered.end + 1;
+            }
+            if start <= range.end {
+                missing.push(Range {
+                    start,
+                    end: range.end,
+                });
+            }
+            missing
+        }
+    }
+}
+
+// sorted, with overlapping and adjacent ranges joined
+fn merge_ranges(mut ranges: Vec<Range>) -> Vec<Range> {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end)
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{existing_ranges, missing_ranges, ranges_to_fetch, remove_temp_files};
+    use crate::cli::config::{test_opts, Config, Dataset, Range, WriteMode};
+    use crate::cli::opts::Opts;
+    use crate::export::export::save_to_file;
+    use crate::fetcher::fetcher::BlockChunk;
+    use serde_json::json;
+    use std::fs;
+
+    fn range(start: u64, end: u64) -> Range {
+        Range { start, end }
+    }
+
+    #[test]
+    fn test_missing_ranges() {
+        let existing = vec![range(200, 299), range(0, 99), range(100, 149)];
+        assert_eq!(
+            missing_ranges(range(50, 499), existing.clone(), WriteMode::SkipExisting),
+            vec![range(150, 199), range(300, 499)]
+        );
+        assert_eq!(
+            missing_ranges(range(50, 499), existing.clone(), WriteMode::Append),
+            vec![range(300, 499)]
+        );
+        assert_eq!(
+            missing_ranges(range(0, 299), existing.clone(), WriteMode::Append),
+            vec![]
+        );
+        assert_eq!(
+            missing_ranges(range(0, 99), existing, WriteMode::Overwrite),
+            vec![range(0, 99)]
+        );
+        assert_eq!(
+            missing_ranges(range(10, 20), vec![], WriteMode::SkipExisting),
+            vec![range(10, 20)]
+        );
+    }
+
+    #[test]
+    fn test_existing_ranges() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        for name in [
+            "ethereum__blocks__0_to_99.csv",
+            "ethereum__blocks__100_to_199.csv",
+            "ethereum__blocks__200_to_299.parquet",
+            "ethereum__transactions__0_to_99.csv",
+            "ethereum__blocks__300_to_x.csv",
+            ".ethereum__blocks__300_to_399.csv.tmp",
+            ".blocks.checkpoint.json.tmp",
+            ".notes.tmp",
+        ] {
+            fs::write(dir.join(name), "")?;
+        }
+        let config: Config = Opts {
+            format: Some("csv".to_owned()),
+            output_dir: Some(dir.to_string_lossy().into_owned()),
+            mode: Some("skip-existing".to_owned()),
+            ..test_opts("blocks", "0:500")
+        }
+        .try_into()?;
+        let mut ranges = existing_ranges(Dataset::Blocks, &config.output)?;
+        ranges.sort_by_key(|range| range.start);
+        assert_eq!(ranges, vec![range(0, 99), range(100, 199)]);
+
+        // only temporary files and the checkpoint left aside of the dataset are removed
+        let removed = remove_temp_files(&[Dataset::Blocks], &config.output)?;
+        assert_eq!(
+            removed,
+            vec![
+                dir.join(".ethereum__blocks__300_to_399.csv.tmp"),
+                dir.join(".blocks.checkpoint.json.tmp"),
+            ]
+        );
+        assert!(dir.join(".notes.tmp").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparse_range() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let config: Config = Opts {
+            format: Some("parquet".to_owned()),
+            chunk_size: Some(100),
+            output_dir: Some(dir.to_string_lossy().into_owned()),
+            mode: Some("skip-existing".to_owned()),
+            ..test_opts("transactions", "0:299")
+        }
+        .try_into()?;
+        // only the first span holds a matching transaction
+        let header = json!({"number": 50, "hash": "0xaa", "timestamp": 1696118400});
+        let spans = [
+            (
+                0,
+                99,
+                vec![json!({"header": header, "transactions": [{"hash": "0xbb"}]})],
+            ),
+            (100, 199, vec![]),
+            (
+                200,
+                299,
+                vec![json!({"header": {"number": 299}, "transactions": []})],
+            ),
+        ];
+        for (start, end, blocks) in spans {
+            let chunk = BlockChunk {
+                start,
+                end,
+                blocks,
+                following: None,
+            };
+            save_to_file(
+                Dataset::Transactions,
+                &["hash".to_owned()],
+                &chunk,
+                None,
+                &config.output,
+            )?;
+        }
+        // spans without transactions are written too, and not fetched again
+        assert!(dir
+            .join("ethereum__transactions__100_to_199.parquet")
+            .exists());
+        let missing = ranges_to_fetch(config.range, &[Dataset::Transactions], &config.output)?;
+        assert_eq!(missing, vec![]);
+        Ok(())
+    }
+}
@@ -1,20 +1,42 @@
 //use polars::prelude::*;
-use crate::cli::config::Dataset;
+use crate::cli::config::{
+    ColumnTypes, Dataset, Destination, OutputConfig, OutputFormat, Partition, WriteMode,
+};
 
-use polars::prelude::{DataFrame, ParquetCompression, ParquetWriter, Series};
-use serde_json::Value;
+use chrono::NaiveDateTime;
+use polars::export::arrow::chunk::Chunk;
+use polars::export::arrow::datatypes::Schema;
+use polars::export::arrow::io::ipc::write::{FileWriter, StreamWriter, WriteOptions};
+use polars::prelude::{AnyValue, CsvWriter, DataFrame, SerWriter, Series};
+use serde_json::{json, Map, Number, Value};
 use std::collections::HashMap;
 
-use crate::export::fields::{create_columns_from_field_data, create_field_data, FieldData};
+use crate::export::columns::arrow_columns;
+use crate::export::fields::{
+    create_columns_from_field_data, create_field_data, FieldData, DATE_FIELD,
+};
+use crate::export::filter::RowFilter;
+use crate::export::metadata::{file_metadata, write_sidecar};
+use crate::export::parquet::write_parquet;
+use crate::fetcher::fetcher::BlockChunk;
 use std::fs::{self, File};
-use std::io::Error;
-use std::path::Path;
+use std::io::{self, BufWriter, Error, Write};
+use std::path::{Path, PathBuf};
+
+/// Suffix of files still being written, see `temp_path`
+pub const TEMP_SUFFIX: &str = ".tmp";
 
 fn blocks_to_dataframe(
     dataset: Dataset,
-    json_data: Vec<Value>,
+    json_data: &[Value],
     fields: Vec<&str>,
+    filter: Option<&RowFilter>,
+    columns: ColumnTypes,
 ) -> Result<DataFrame, Error> {
+    let mut fields = fields;
+    if columns.date {
+        fields.push(DATE_FIELD);
+    }
     //let block_fields: Vec<(&str, FieldData)> = vec![(fields[0], FieldData::Hash(vec![]))];
     let block_fields: Vec<(&str, FieldData)> = fields
         .iter()
@@ -26,23 +48,94 @@ fn blocks_to_dataframe(
         .map(|(name, data)| (name.to_string(), data))
         .collect();
     //put loop inside func, return mutable reference to fieldmap
-    field_map = process_json_object(json_data, field_map, &fields, &dataset).unwrap();
+    field_map = process_json_object(json_data, field_map, &fields, &dataset)?;
     //create series from fields
 
-    let columns: Vec<Series> = create_columns_from_field_data(&field_map, &fields);
+    let columns: Vec<Series> =
+        create_columns_from_field_data(&field_map, &fields, columns.quantity);
     // Create DataFrames
     //let df = DataFrame::new(columns).unwrap();
-    let df = DataFrame::new(columns)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
-    println!("DF: {:?}", df);
+    let mut df = DataFrame::new(columns).map_err(|e| std::io::Error::other(e.to_string()))?;
+    if let Some(filter) = filter {
+        df = filter
+            .apply(&df)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+    }
     Ok(df)
 }
-//pass fields here
+/// Rows of one dataset in a chunk, for sinks that take dataframes directly
+pub fn chunk_to_dataframe(
+    dataset: Dataset,
+    fields_vec: &[String],
+    json_data: &[Value],
+    filter: Option<&RowFilter>,
+    columns: ColumnTypes,
+) -> Result<DataFrame, Error> {
+    let fields = fields_vec.iter().map(|s| s.as_str()).collect();
+    blocks_to_dataframe(dataset, json_data, fields, filter, columns)
+}
+
+/// Writes the `dataset` rows of `chunk` to files, returning their paths
 pub fn save_to_file(
     dataset: Dataset,
-    fields_vec: &Vec<String>,
-    json_data: Vec<Value>,
-    counter: usize,
+    fields_vec: &[String],
+    chunk: &BlockChunk,
+    filter: Option<&RowFilter>,
+    output: &OutputConfig,
+) -> Result<Vec<PathBuf>, Error> {
+    let folder = match &output.destination {
+        Destination::Directory(dir) => Path::new(dir),
+        destination => {
+            return Err(std::io::Error::other(format!(
+                "{:?} is not a directory",
+                destination
+            )))
+        }
+    };
+    // related data lands next to the file of the matched items, under the same range
+    let parts = match output.partition {
+        Some(partition) => partition_blocks(chunk, partition)?
+            .into_iter()
+            .map(|(dir, start, end, blocks)| {
+                let dir = folder.join(format!("dataset={}", dataset.name())).join(dir);
+                (dir, start, end, blocks)
+            })
+            .collect(),
+        None => vec![(
+            folder.to_path_buf(),
+            chunk.start,
+            chunk.end,
+            &chunk.blocks[..],
+        )],
+    };
+    let mut files = Vec::new();
+    for (dir, start, end, json_data) in parts {
+        if !dir.exists() {
+            fs::create_dir_all(&dir)?;
+        }
+        let file_path = dir.join(output.file_name(dataset, start, end));
+        // completed files are never replaced unless overwriting
+        if output.mode != WriteMode::Overwrite && file_path.exists() {
+            eprintln!("Keeping existing {}", file_path.display());
+            continue;
+        }
+        let metadata = file_metadata(dataset, fields_vec, start, end, json_data, output);
+        write_file(
+            dataset, fields_vec, json_data, filter, output, &file_path, metadata,
+        )?;
+        files.push(file_path);
+    }
+    Ok(files)
+}
+
+fn write_file(
+    dataset: Dataset,
+    fields_vec: &[String],
+    json_data: &[Value],
+    filter: Option<&RowFilter>,
+    output: &OutputConfig,
+    file_path: &Path,
+    metadata: Vec<(String, String)>,
 ) -> Result<(), Error> {
     //let fields = vec!["hash", "number", "parentHash", "timestamp", "miner", "stateRoot", "transactionsRoot", "receiptsRoot", "gasUsed", "extraData", "baseFeePerGas", "logsBloom", "totalDifficulty", "size"];
     //let json_element = json_data[0].get("header").unwrap().clone();
@@ -50,68 +143,751 @@ pub fn save_to_file(
     //let tx = json_data[0].get("transactions").unwrap().clone();
 
     //let df = blocks_to_dataframe(json_data, fields)?;
-    let df = blocks_to_dataframe(dataset, json_data, fields)?;
-    let folder = Path::new("../data");
+    let mut df = blocks_to_dataframe(dataset, json_data, fields, filter, output.columns)?;
+    // readers never see a partial file, it only gets its name once complete
+    let temp_path = temp_path(file_path);
+    let file = File::create(&temp_path)?;
+    // csv and json have no place for metadata, it goes beside them before they are named
+    let sidecar = matches!(
+        output.format,
+        OutputFormat::Csv(_) | OutputFormat::Json | OutputFormat::Ndjson
+    );
+    let written = write_rows(
+        &mut df, json_data, dataset, fields_vec, output, &file, &metadata,
+    )
+    .and_then(|_| file.sync_all())
+    .and_then(|_| match sidecar {
+        true => write_sidecar(file_path, &metadata),
+        false => Ok(()),
+    })
+    .and_then(|_| fs::rename(&temp_path, file_path))
+    .and_then(|_| sync_parent(file_path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}
+
+fn write_rows(
+    df: &mut DataFrame,
+    json_data: &[Value],
+    dataset: Dataset,
+    fields_vec: &[String],
+    output: &OutputConfig,
+    file: &File,
+    metadata: &[(String, String)],
+) -> Result<(), Error> {
+    let mut file = BufWriter::new(file);
+    match &output.format {
+        OutputFormat::Json | OutputFormat::Ndjson => {
+            let rows = if output.nested {
+                nested_blocks(json_data, fields_vec, output.nested_transactions.as_deref())
+            } else {
+                json_rows(df)
+            };
+            write_json_rows(&mut file, &rows, &output.format, 0)?;
+            close_json_rows(&mut file, &output.format, rows.len())?;
+        }
+        format => write_dataframe(
+            df,
+            &mut file,
+            format,
+            true,
+            dataset,
+            output.columns,
+            metadata,
+        )?,
+    }
+    file.flush()
+}
+
+/// Hidden sibling of `path` a file is written to before it is renamed into place
+pub fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}{}", name, TEMP_SUFFIX))
+}
 
-    if !folder.exists() {
-        fs::create_dir_all(folder)?;
+/// Syncs the directory holding `path`, so a rename into it survives a crash
+pub fn sync_parent(path: &Path) -> Result<(), Error> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+fn partition_dir(partition: Partition, header: &Value) -> String {
+    let timestamp = header["timestamp"].as_i64().unwrap_or_default();
+    let date = NaiveDateTime::from_timestamp_opt(timestamp, 0).unwrap_or_default();
+    match partition {
+        Partition::Date => format!("date={}", date.format("%Y-%m-%d")),
+        Partition::Month => format!("month={}", date.format("%Y-%m")),
+        Partition::BlockBucket(size) => {
+            let number = header["number"].as_u64().unwrap_or_default();
+            format!("block_bucket={}", number / size * size)
+        }
+    }
+}
+
+// partition directory, first and last block number and blocks of a file
+type Part<'a> = (String, u64, u64, &'a [Value]);
+
+/// Splits a chunk into runs of blocks sharing a partition directory. Block
+/// buckets get one part each, clamped to the bucket and empty when it has no
+/// blocks. Block timestamps never decrease, so every date gets one contiguous
+/// run spanning its first to last block; the spans without blocks between and
+/// around the runs get empty parts filed with the block following them.
+fn partition_blocks(chunk: &BlockChunk, partition: Partition) -> Result<Vec<Part<'_>>, Error> {
+    let numbers = chunk
+        .blocks
+        .iter()
+        .map(|block| {
+            block["header"]["number"]
+                .as_u64()
+                .ok_or_else(|| Error::other("Block without a number in the chunk"))
+        })
+        .collect::<Result<Vec<u64>, Error>>()?;
+    if let Partition::BlockBucket(size) = partition {
+        let mut parts = Vec::new();
+        let mut rest = 0;
+        for bucket in chunk.start / size..=chunk.end / size {
+            let first = bucket * size;
+            let last = first.saturating_add(size - 1);
+            let split = rest + numbers[rest..].partition_point(|number| *number <= last);
+            parts.push((
+                format!("block_bucket={}", first),
+                chunk.start.max(first),
+                chunk.end.min(last),
+                &chunk.blocks[rest..split],
+            ));
+            rest = split;
+        }
+        return Ok(parts);
+    }
+    let mut parts: Vec<Part> = Vec::new();
+    let mut covered = chunk.start;
+    let mut run_start = 0;
+    for i in 0..chunk.blocks.len() {
+        let dir = partition_dir(partition, &chunk.blocks[i]["header"]);
+        let next = chunk
+            .blocks
+            .get(i + 1)
+            .map(|block| partition_dir(partition, &block["header"]));
+        if next.as_ref() == Some(&dir) {
+            continue;
+        }
+        let (first, last) = (numbers[run_start], numbers[i]);
+        if covered < first {
+            parts.push((dir.clone(), covered, first - 1, &chunk.blocks[..0]));
+        }
+        parts.push((dir, first, last, &chunk.blocks[run_start..=i]));
+        covered = last + 1;
+        run_start = i + 1;
+    }
+    if covered <= chunk.end {
+        let header = match (&chunk.following, chunk.blocks.last()) {
+            (Some(header), _) => header.clone(),
+            (None, Some(block)) => block["header"].clone(),
+            (None, None) => json!({}),
+        };
+        let dir = partition_dir(partition, &header);
+        parts.push((dir, covered, chunk.end, &chunk.blocks[..0]));
+    }
+    Ok(parts)
+}
+
+/// Streams chunks to stdout, json output stays a single array and arrow output
+/// a single stream across all chunks
+#[derive(Default)]
+pub struct StdoutWriter {
+    chunks: usize,
+    rows: usize,
+    stream: Option<StreamWriter<io::Stdout>>,
+}
+
+impl StdoutWriter {
+    pub fn write_chunk(
+        &mut self,
+        dataset: Dataset,
+        fields_vec: &[String],
+        json_data: &[Value],
+        filter: Option<&RowFilter>,
+        output: &OutputConfig,
+    ) -> Result<(), Error> {
+        let fields = fields_vec.iter().map(|s| s.as_str()).collect();
+        let mut df = blocks_to_dataframe(dataset, json_data, fields, filter, output.columns)?;
+        let mut stdout = io::stdout().lock();
+        match output.format {
+            OutputFormat::Json | OutputFormat::Ndjson => {
+                let rows = if output.nested {
+                    nested_blocks(json_data, fields_vec, output.nested_transactions.as_deref())
+                } else {
+                    json_rows(&df)
+                };
+                write_json_rows(&mut stdout, &rows, &output.format, self.rows)?;
+                self.rows += rows.len();
+            }
+            OutputFormat::ArrowStream => {
+                let (schema, columns) = arrow_columns(&mut df, dataset, output.columns)?;
+                let stream = match &mut self.stream {
+                    Some(stream) => stream,
+                    None => self
+                        .stream
+                        .insert(start_arrow_stream(io::stdout(), &schema)?),
+                };
+                stream
+                    .write(&Chunk::new(columns), None)
+                    .map_err(std::io::Error::other)?;
+                io::stdout().flush()?;
+            }
+            // the header is only written once, before the first chunk
+            _ => write_dataframe(
+                &mut df,
+                &mut stdout,
+                &output.format,
+                self.chunks == 0,
+                dataset,
+                output.columns,
+                &[],
+            )?,
+        }
+        self.chunks += 1;
+        stdout.flush()
+    }
+
+    pub fn finish(&mut self, output: &OutputConfig) -> Result<(), Error> {
+        if let Some(stream) = &mut self.stream {
+            stream.finish().map_err(std::io::Error::other)?;
+        }
+        let mut stdout = io::stdout().lock();
+        close_json_rows(&mut stdout, &output.format, self.rows)?;
+        stdout.flush()
+    }
+}
+
+fn write_dataframe<W: Write>(
+    df: &mut DataFrame,
+    writer: W,
+    format: &OutputFormat,
+    header: bool,
+    dataset: Dataset,
+    columns: ColumnTypes,
+    metadata: &[(String, String)],
+) -> Result<(), Error> {
+    match format {
+        OutputFormat::Parquet(options) => {
+            write_parquet(df, writer, options, dataset, columns, metadata.to_vec())
+        }
+        OutputFormat::Csv(options) => CsvWriter::new(writer)
+            .has_header(options.header && header)
+            .with_delimiter(options.delimiter)
+            .with_quoting_char(options.quote)
+            .finish(df)
+            .map_err(std::io::Error::other),
+        OutputFormat::Arrow => {
+            let (schema, columns) = arrow_columns(df, dataset, columns)?;
+            let schema = schema.with_metadata(metadata.iter().cloned().collect());
+            let options = WriteOptions { compression: None };
+            let mut file = FileWriter::try_new(writer, &schema, None, options)
+                .map_err(std::io::Error::other)?;
+            file.write(&Chunk::new(columns), None)
+                .map_err(std::io::Error::other)?;
+            file.finish().map_err(std::io::Error::other)
+        }
+        OutputFormat::ArrowStream => {
+            let (schema, columns) = arrow_columns(df, dataset, columns)?;
+            let schema = schema.with_metadata(metadata.iter().cloned().collect());
+            let mut stream = start_arrow_stream(writer, &schema)?;
+            stream
+                .write(&Chunk::new(columns), None)
+                .map_err(std::io::Error::other)?;
+            stream.finish().map_err(std::io::Error::other)
+        }
+        OutputFormat::Json | OutputFormat::Ndjson | OutputFormat::Sqlite | OutputFormat::Duckdb => {
+            Err(std::io::Error::other(format!(
+                "{:?} is not written as a file",
+                format
+            )))
+        }
     }
-    //TODO name file with blocks num and data name
-    let file_path = format!("../data/my_dataframe_{}.parquet", counter);
+}
+
+fn start_arrow_stream<W: Write>(writer: W, schema: &Schema) -> Result<StreamWriter<W>, Error> {
+    let mut stream = StreamWriter::new(writer, WriteOptions { compression: None });
+    stream.start(schema, None).map_err(std::io::Error::other)?;
+    Ok(stream)
+}
+
+// rows as json objects
+fn json_rows(df: &DataFrame) -> Vec<Value> {
+    (0..df.height())
+        .map(|row| {
+            let object: Map<String, Value> = df
+                .get_columns()
+                .iter()
+                .map(|series| (series.name().to_owned(), any_value_to_json(series.get(row))))
+                .collect();
+            Value::Object(object)
+        })
+        .collect()
+}
 
-    let file =
-        File::create(file_path).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+// blocks with the header cut down to `fields`, the header fields the fetcher always
+// requests dropped, and their transactions to `transaction_fields` when included
+fn nested_blocks(
+    json_data: &[Value],
+    fields: &[String],
+    transaction_fields: Option<&[String]>,
+) -> Vec<Value> {
+    let project = |item: &Value, fields: &[String]| -> Value {
+        let object: Map<String, Value> = fields
+            .iter()
+            .map(|field| (field.clone(), item[field.as_str()].clone()))
+            .collect();
+        Value::Object(object)
+    };
+    json_data
+        .iter()
+        .map(|block| {
+            let mut nested = Map::new();
+            nested.insert("header".to_owned(), project(&block["header"], fields));
+            if let Some(transaction_fields) = transaction_fields {
+                let transactions = block["transactions"]
+                    .as_array()
+                    .map(|txs| {
+                        txs.iter()
+                            .map(|tx| project(tx, transaction_fields))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                nested.insert("transactions".to_owned(), Value::Array(transactions));
+            }
+            Value::Object(nested)
+        })
+        .collect()
+}
 
-    ParquetWriter::new(file)
-        .with_compression(ParquetCompression::Snappy)
-        .finish(&mut df.clone())
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+fn any_value_to_json(value: AnyValue) -> Value {
+    match value {
+        AnyValue::Null => Value::Null,
+        AnyValue::Boolean(value) => Value::Bool(value),
+        AnyValue::Utf8(value) => Value::String(value.to_owned()),
+        AnyValue::UInt8(value) => value.into(),
+        AnyValue::UInt16(value) => value.into(),
+        AnyValue::UInt32(value) => value.into(),
+        AnyValue::UInt64(value) => value.into(),
+        AnyValue::Int8(value) => value.into(),
+        AnyValue::Int16(value) => value.into(),
+        AnyValue::Int32(value) => value.into(),
+        AnyValue::Int64(value) => value.into(),
+        AnyValue::Float32(value) => {
+            Number::from_f64(value as f64).map_or(Value::Null, Value::Number)
+        }
+        AnyValue::Float64(value) => Number::from_f64(value).map_or(Value::Null, Value::Number),
+        value => Value::String(value.to_string()),
+    }
+}
 
+// `written` rows came before, so json arrays can continue across chunks
+fn write_json_rows<W: Write>(
+    writer: &mut W,
+    rows: &[Value],
+    format: &OutputFormat,
+    written: usize,
+) -> Result<(), Error> {
+    for (i, row) in rows.iter().enumerate() {
+        match (format, written + i) {
+            (OutputFormat::Json, 0) => writer.write_all(b"[\n")?,
+            (OutputFormat::Json, _) => writer.write_all(b",\n")?,
+            _ => {}
+        }
+        serde_json::to_writer(&mut *writer, row)?;
+        if *format == OutputFormat::Ndjson {
+            writer.write_all(b"\n")?;
+        }
+    }
     Ok(())
 }
 
+fn close_json_rows<W: Write>(
+    writer: &mut W,
+    format: &OutputFormat,
+    rows: usize,
+) -> Result<(), Error> {
+    match (format, rows) {
+        (OutputFormat::Json, 0) => writer.write_all(b"[]\n"),
+        (OutputFormat::Json, _) => writer.write_all(b"\n]\n"),
+        _ => Ok(()),
+    }
+}
+
 fn process_json_object(
-    json_data: Vec<Value>,
+    json_data: &[Value],
     mut field_map: HashMap<String, FieldData>,
     fields: &[&str],
     dataset: &Dataset,
 ) -> Result<HashMap<String, FieldData>, Error> {
     for json_obj in json_data {
+        // unparseable values fail the chunk, naming where they came from
+        let add = |data: &mut FieldData, field: &str, value: &Value| {
+            data.add_value(value).map_err(|e| {
+                Error::other(format!(
+                    "{} of block {}: {}",
+                    field, json_obj["header"]["number"], e
+                ))
+            })
+        };
         // Extracting logic to process a single json object
         match dataset {
             Dataset::Blocks => {
                 if let Some(header) = json_obj.get("header") {
                     //check types here TODO
-                    fields.iter().for_each(|field| {
+                    for field in fields {
                         if let Some(data) = field_map.get_mut(*field) {
-                            if let Some(value) = header.get(*field) {
-                                //println!("Value: {:?}", value);
-                                data.add_value(value);
-                            }
+                            // absent fields are pushed as null to keep columns aligned
+                            let source = match *field {
+                                DATE_FIELD => "timestamp",
+                                field => field,
+                            };
+                            add(data, field, header.get(source).unwrap_or(&Value::Null))?;
                         }
-                    });
+                    }
                     //println!("FIELD MAP: {:?}", field_map);
                 }
             }
             Dataset::Transactions => {
-                if let Some(tx_list) = json_obj.get("transactions") {
+                if let Some(tx_list) = json_obj.get("transactions").and_then(|txs| txs.as_array()) {
                     //check types here TODO
-                    fields.iter().for_each(|field| {
+                    for field in fields {
                         //Same for logs
                         if let Some(data) = field_map.get_mut(*field) {
-                            for tx in tx_list.as_array().unwrap() {
-                                if let Some(value) = tx.get(*field) {
-                                    data.add_value(value);
-                                }
+                            for tx in tx_list {
+                                let value = match *field {
+                                    "blockNumber" => &json_obj["header"]["number"],
+                                    DATE_FIELD => &json_obj["header"]["timestamp"],
+                                    field => tx.get(field).unwrap_or(&Value::Null),
+                                };
+                                add(data, field, value)?;
                             }
                         }
-                    });
+                    }
                     //println!("FIELD MAP: {:?}", field_map);
                 }
             }
-            _ => panic!("Dataset not found"),
+            Dataset::Logs | Dataset::Traces | Dataset::StateDiffs => {
+                let list = match dataset {
+                    Dataset::Logs => "logs",
+                    Dataset::Traces => "traces",
+                    _ => "stateDiffs",
+                };
+                if let Some(items) = json_obj.get(list).and_then(|items| items.as_array()) {
+                    for field in fields {
+                        if let Some(data) = field_map.get_mut(*field) {
+                            for item in items {
+                                // missing values are pushed as null to keep columns aligned
+                                let value = match *field {
+                                    "blockNumber" => &json_obj["header"]["number"],
+                                    DATE_FIELD => &json_obj["header"]["timestamp"],
+                                    _ => item_value(dataset, item, field),
+                                };
+                                add(data, field, value)?;
+                            }
+                        }
+                    }
+                }
+            }
         }
     }
 
     Ok(field_map)
 }
+
+// topics and trace actions are nested in the archive response
+fn item_value<'a>(dataset: &Dataset, item: &'a Value, field: &str) -> &'a Value {
+    match (dataset, field) {
+        (Dataset::Logs, "topic0") => &item["topics"][0],
+        (Dataset::Logs, "topic1") => &item["topics"][1],
+        (Dataset::Logs, "topic2") => &item["topics"][2],
+        (Dataset::Logs, "topic3") => &item["topics"][3],
+        (Dataset::Traces, "from" | "value") => &item["action"][field],
+        (Dataset::Traces, "to") => match &item["action"]["to"] {
+            Value::Null => &item["result"]["address"],
+            to => to,
+        },
+        (Dataset::Traces, "input") => match &item["action"]["input"] {
+            Value::Null => &item["action"]["init"],
+            input => input,
+        },
+        (Dataset::Traces, "gasUsed") => &item["result"]["gasUsed"],
+        _ => &item[field],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        blocks_to_dataframe, close_json_rows, json_rows, nested_blocks, partition_blocks,
+        write_dataframe, write_json_rows,
+    };
+    use crate::cli::config::{ColumnTypes, Dataset, OutputFormat, Partition};
+    use crate::fetcher::fetcher::BlockChunk;
+    use polars::export::arrow::io::ipc::read::{
+        read_file_metadata, read_stream_metadata, StreamReader,
+    };
+    use polars::prelude::{DataFrame, IpcReader, NamedFrom, SerReader, Series};
+    use serde_json::{json, Value};
+    use std::io::{Cursor, Error};
+
+    fn sample() -> DataFrame {
+        DataFrame::new(vec![
+            Series::new("number", &[1u64, 2]),
+            Series::new("hash", &["0xaa", "0xbb"]),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_arrow_formats() -> Result<(), Error> {
+        // run metadata goes in the schema, like in the parquet footer
+        let run = [("range".to_owned(), "1:2".to_owned())];
+        let mut file = Vec::new();
+        write_dataframe(
+            &mut sample(),
+            &mut file,
+            &OutputFormat::Arrow,
+            true,
+            Dataset::Blocks,
+            ColumnTypes::default(),
+            &run,
+        )?;
+        let metadata = read_file_metadata(&mut Cursor::new(&file)).unwrap();
+        assert_eq!(metadata.schema.metadata["range"], "1:2");
+        let df = IpcReader::new(Cursor::new(file)).finish().unwrap();
+        assert!(df.frame_equal(&sample()));
+
+        let mut stream = Vec::new();
+        write_dataframe(
+            &mut sample(),
+            &mut stream,
+            &OutputFormat::ArrowStream,
+            true,
+            Dataset::Blocks,
+            ColumnTypes::default(),
+            &run,
+        )?;
+        let mut reader = Cursor::new(stream);
+        let metadata = read_stream_metadata(&mut reader).unwrap();
+        assert_eq!(metadata.schema.metadata["range"], "1:2");
+        let rows: usize = StreamReader::new(reader, metadata)
+            .map(|state| state.unwrap().unwrap().len())
+            .sum();
+        assert_eq!(rows, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_partition_blocks() -> Result<(), Error> {
+        let spans = |chunk: &BlockChunk, partition: Partition| -> Result<_, Error> {
+            Ok(partition_blocks(chunk, partition)?
+                .into_iter()
+                .map(|(dir, start, end, blocks)| (dir, start, end, blocks.len()))
+                .collect::<Vec<_>>())
+        };
+        // 2023-09-30 23:59:48 and 2023-10-01 00:00:00 and 00:00:12
+        let blocks = [
+            (18000010u64, 1696118388u64),
+            (18000020, 1696118400),
+            (18000021, 1696118412),
+        ]
+        .iter()
+        .map(|(number, timestamp)| json!({"header": {"number": number, "timestamp": timestamp}}))
+        .collect();
+        let chunk = BlockChunk {
+            start: 18000000,
+            end: 18000099,
+            blocks,
+            following: None,
+        };
+        // files of a date hold its blocks only, the spans around them are left empty
+        assert_eq!(
+            spans(&chunk, Partition::Date)?,
+            vec![
+                ("date=2023-09-30".to_owned(), 18000000, 18000009, 0),
+                ("date=2023-09-30".to_owned(), 18000010, 18000010, 1),
+                ("date=2023-10-01".to_owned(), 18000011, 18000019, 0),
+                ("date=2023-10-01".to_owned(), 18000020, 18000021, 2),
+                ("date=2023-10-01".to_owned(), 18000022, 18000099, 0),
+            ]
+        );
+        assert_eq!(
+            spans(&chunk, Partition::BlockBucket(1000))?,
+            vec![("block_bucket=18000000".to_owned(), 18000000, 18000099, 3)]
+        );
+
+        // a span without blocks goes with the block after it
+        let empty = BlockChunk {
+            start: 18000100,
+            end: 18000199,
+            blocks: Vec::new(),
+            following: Some(json!({"number": 18000250, "timestamp": 1696121412u64})),
+        };
+        assert_eq!(
+            spans(&empty, Partition::Date)?,
+            vec![("date=2023-10-01".to_owned(), 18000100, 18000199, 0)]
+        );
+
+        // sparse blocks never stretch a file past its bucket, empty buckets get empty files
+        let sparse = BlockChunk {
+            start: 250,
+            end: 2600,
+            blocks: vec![
+                json!({"header": {"number": 500}}),
+                json!({"header": {"number": 2500}}),
+            ],
+            following: None,
+        };
+        assert_eq!(
+            spans(&sparse, Partition::BlockBucket(1000))?,
+            vec![
+                ("block_bucket=0".to_owned(), 250, 999, 1),
+                ("block_bucket=1000".to_owned(), 1000, 1999, 0),
+                ("block_bucket=2000".to_owned(), 2000, 2600, 1),
+            ]
+        );
+        let broken = BlockChunk {
+            blocks: vec![json!({"header": {}})],
+            ..sparse
+        };
+        assert!(partition_blocks(&broken, Partition::BlockBucket(1000)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_rows() -> Result<(), Error> {
+        let rows = json_rows(&sample());
+        assert_eq!(rows[1], json!({"number": 2, "hash": "0xbb"}));
+
+        // a second chunk continues the array of the first
+        let mut out = Vec::new();
+        write_json_rows(&mut out, &rows[..1], &OutputFormat::Json, 0)?;
+        write_json_rows(&mut out, &rows[1..], &OutputFormat::Json, 1)?;
+        close_json_rows(&mut out, &OutputFormat::Json, 2)?;
+        let parsed: Value = serde_json::from_slice(&out)?;
+        assert_eq!(parsed, Value::Array(rows.clone()));
+
+        let mut out = Vec::new();
+        write_json_rows(&mut out, &rows, &OutputFormat::Ndjson, 0)?;
+        assert_eq!(String::from_utf8_lossy(&out).lines().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_blocks() {
+        let blocks = [json!({
+            "header": {"number": 1, "hash": "0xaa", "parentHash": "0x99", "timestamp": 12},
+            "transactions": [{"hash": "0xbb", "value": "0x0", "transactionIndex": 0}],
+        })];
+        let fields = ["number".to_owned(), "miner".to_owned()];
+        let tx_fields = ["hash".to_owned(), "value".to_owned()];
+        // headers keep only the selected fields, missing ones as null
+        assert_eq!(
+            nested_blocks(&blocks, &fields, None),
+            vec![json!({"header": {"number": 1, "miner": null}})]
+        );
+        assert_eq!(
+            nested_blocks(&blocks, &fields, Some(&tx_fields)),
+            vec![json!({
+                "header": {"number": 1, "miner": null},
+                "transactions": [{"hash": "0xbb", "value": "0x0"}],
+            })]
+        );
+    }
+
+    #[test]
+    fn test_nullable_columns() -> Result<(), Error> {
+        // a contract creation without `to` and a legacy transaction without fee caps
+        let blocks = [json!({
+            "header": {"number": 1},
+            "transactions": [
+                {"hash": "0xaa", "value": "0x0", "maxFeePerGas": "0x3b9aca00"},
+                {"hash": "0xbb", "to": "0xcc", "value": "0x1"},
+            ],
+        })];
+        let fields = vec!["hash", "to", "maxFeePerGas", "yParity", "blockNumber"];
+        let df = blocks_to_dataframe(
+            Dataset::Transactions,
+            &blocks,
+            fields,
+            None,
+            ColumnTypes::default(),
+        )?;
+        assert_eq!(df.height(), 2);
+        assert_eq!(df.column("to").unwrap().null_count(), 1);
+        assert_eq!(df.column("maxFeePerGas").unwrap().null_count(), 1);
+        assert_eq!(df.column("yParity").unwrap().null_count(), 2);
+        assert_eq!(df.column("blockNumber").unwrap().null_count(), 0);
+
+        let blocks = [json!({"header": {"number": 1, "hash": "0xaa"}})];
+        let df = blocks_to_dataframe(
+            Dataset::Blocks,
+            &blocks,
+            vec!["number", "hash", "baseFeePerGas"],
+            None,
+            ColumnTypes::default(),
+        )?;
+        assert_eq!(df.column("baseFeePerGas").unwrap().null_count(), 1);
+        Ok(())
+    }
+    #[test]
+    fn test_invalid_values() -> Result<(), Error> {
+        // only nulls become nulls, values that do not parse fail naming field and block
+        let blocks = [json!({
+            "header": {"number": 7},
+            "transactions": [{"gas": "0x5208", "value": null}, {"gas": "21000", "value": "0x1"}],
+        })];
+        let error = blocks_to_dataframe(
+            Dataset::Transactions,
+            &blocks,
+            vec!["gas", "value"],
+            None,
+            ColumnTypes::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "gas of block 7: \"21000\" is not a number"
+        );
+        let blocks = [json!({"header": {"number": 7, "baseFeePerGas": true}})];
+        let error = blocks_to_dataframe(
+            Dataset::Blocks,
+            &blocks,
+            vec!["number", "baseFeePerGas"],
+            None,
+            ColumnTypes::default(),
+        )
+        .unwrap_err();
+        assert!(error.to_string().starts_with("baseFeePerGas of block 7"));
+
+        // trace gas is numeric like the gas of blocks and transactions
+        let blocks = [json!({
+            "header": {"number": 7},
+            "traces": [{"result": {"gasUsed": "0x5208"}}, {"result": {}}],
+        })];
+        let df = blocks_to_dataframe(
+            Dataset::Traces,
+            &blocks,
+            vec!["gasUsed"],
+            None,
+            ColumnTypes::default(),
+        )?;
+        let gas: Vec<_> = df
+            .column("gasUsed")
+            .unwrap()
+            .u64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(gas, vec![Some(21000), None]);
+        Ok(())
+    }
+}
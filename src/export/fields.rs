@@ -2,145 +2,355 @@ use std::collections::HashMap;
 
 use polars::prelude::{NamedFrom, Series};
 
-use crate::cli::config::Dataset;
+use crate::cli::config::{Dataset, Quantity};
+use crate::export::u256::U256;
+use chrono::NaiveDateTime;
+use std::io::Error;
+
+/// Day of the block timestamp, derived for rows of every dataset with `--date-column`
+pub const DATE_FIELD: &str = "date";
 
 #[derive(Debug)]
 pub enum FieldData {
+    Date(Vec<Option<String>>),
     BlocksData(BlockFieldData),
     TransactionsData(TransactionsFieldData),
+    LogsData(LogsFieldData),
+    TracesData(TracesFieldData),
+    StateDiffsData(StateDiffsFieldData),
 }
 
 #[derive(Debug)]
 pub enum BlockFieldData {
-    Hash(Vec<String>),
-    Number(Vec<u64>),
-    ParentHash(Vec<String>),
-    Timestamp(Vec<u64>),
-    Miner(Vec<String>),
-    StateRoot(Vec<String>),
-    TransactionsRoot(Vec<String>),
-    ReceiptsRoot(Vec<String>),
-    GasUsed(Vec<u64>),
-    ExtraData(Vec<String>),
-    BaseFeePerGas(Vec<u64>),
-    LogsBloom(Vec<String>),
-    TotalDifficulty(Vec<u64>),
-    Size(Vec<u64>),
+    Hash(Vec<Option<String>>),
+    Number(Vec<Option<u64>>),
+    ParentHash(Vec<Option<String>>),
+    Timestamp(Vec<Option<u64>>),
+    Miner(Vec<Option<String>>),
+    StateRoot(Vec<Option<String>>),
+    TransactionsRoot(Vec<Option<String>>),
+    ReceiptsRoot(Vec<Option<String>>),
+    GasUsed(Vec<Option<u64>>),
+    ExtraData(Vec<Option<String>>),
+    BaseFeePerGas(Vec<Option<U256>>),
+    LogsBloom(Vec<Option<String>>),
+    TotalDifficulty(Vec<Option<U256>>),
+    Size(Vec<Option<u64>>),
 }
 #[derive(Debug)]
 pub enum TransactionsFieldData {
-    Id(Vec<String>),
-    TransactionIndex(Vec<u64>),
-    From(Vec<String>),
-    To(Vec<String>),
-    Hash(Vec<String>),
-    Gas(Vec<u64>),
-    GasPrice(Vec<u64>),
-    MaxFeePerGas(Vec<u64>),
-    MaxPriorityFeePerGas(Vec<u64>),
-    Input(Vec<String>),
-    Nonce(Vec<u64>),
-    Value(Vec<String>),
-    V(Vec<u64>),
-    R(Vec<String>),
-    S(Vec<String>),
-    YParity(Vec<u64>),
-    ChainId(Vec<u64>),
-    GasUsed(Vec<u64>),
-    CumulativeGasUsed(Vec<u64>),
-    EffectiveGasPrice(Vec<u64>),
-    ContractAddress(Vec<String>),
-    Type(Vec<u64>),
-    Status(Vec<u64>),
-    Sighash(Vec<String>),
+    Id(Vec<Option<String>>),
+    TransactionIndex(Vec<Option<u64>>),
+    From(Vec<Option<String>>),
+    To(Vec<Option<String>>),
+    Hash(Vec<Option<String>>),
+    Gas(Vec<Option<u64>>),
+    GasPrice(Vec<Option<U256>>),
+    MaxFeePerGas(Vec<Option<U256>>),
+    MaxPriorityFeePerGas(Vec<Option<U256>>),
+    Input(Vec<Option<String>>),
+    Nonce(Vec<Option<u64>>),
+    Value(Vec<Option<U256>>),
+    V(Vec<Option<u64>>),
+    R(Vec<Option<String>>),
+    S(Vec<Option<String>>),
+    YParity(Vec<Option<u64>>),
+    ChainId(Vec<Option<u64>>),
+    GasUsed(Vec<Option<u64>>),
+    CumulativeGasUsed(Vec<Option<u64>>),
+    EffectiveGasPrice(Vec<Option<U256>>),
+    ContractAddress(Vec<Option<String>>),
+    Type(Vec<Option<u64>>),
+    Status(Vec<Option<u64>>),
+    Sighash(Vec<Option<String>>),
+    BlockNumber(Vec<Option<u64>>),
+}
+#[derive(Debug)]
+pub enum LogsFieldData {
+    BlockNumber(Vec<Option<u64>>),
+    LogIndex(Vec<Option<u64>>),
+    TransactionIndex(Vec<Option<u64>>),
+    TransactionHash(Vec<Option<String>>),
+    Address(Vec<Option<String>>),
+    Data(Vec<Option<String>>),
+    Topic0(Vec<Option<String>>),
+    Topic1(Vec<Option<String>>),
+    Topic2(Vec<Option<String>>),
+    Topic3(Vec<Option<String>>),
+}
+#[derive(Debug)]
+pub enum TracesFieldData {
+    BlockNumber(Vec<Option<u64>>),
+    TransactionIndex(Vec<Option<u64>>),
+    TraceAddress(Vec<Option<String>>),
+    Type(Vec<Option<String>>),
+    Subtraces(Vec<Option<u64>>),
+    Error(Vec<Option<String>>),
+    From(Vec<Option<String>>),
+    To(Vec<Option<String>>),
+    Value(Vec<Option<U256>>),
+    Input(Vec<Option<String>>),
+    GasUsed(Vec<Option<u64>>),
+}
+#[derive(Debug)]
+pub enum StateDiffsFieldData {
+    BlockNumber(Vec<Option<u64>>),
+    TransactionIndex(Vec<Option<u64>>),
+    Address(Vec<Option<String>>),
+    Key(Vec<Option<String>>),
+    Kind(Vec<Option<String>>),
+    Prev(Vec<Option<String>>),
+    Next(Vec<Option<String>>),
+}
+
+// absent values and nulls are written as nulls, never as zero or empty text
+fn text(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Null => None,
+        value => Some(value.to_string()),
+    }
+}
+
+fn invalid(value: &serde_json::Value, kind: &str) -> Error {
+    Error::other(format!("{} is not {}", value, kind))
+}
+
+// numbers come as json numbers or hex strings, anything else but null is an error
+fn number(value: &serde_json::Value) -> Result<Option<u64>, Error> {
+    let number = match value {
+        serde_json::Value::Null => return Ok(None),
+        serde_json::Value::String(s) => s
+            .strip_prefix("0x")
+            .and_then(|digits| u64::from_str_radix(digits, 16).ok()),
+        value => value.as_u64(),
+    };
+    number.map(Some).ok_or_else(|| invalid(value, "a number"))
+}
+
+// some archives send fractional unix seconds
+fn timestamp(value: &serde_json::Value) -> Result<Option<u64>, Error> {
+    match value {
+        serde_json::Value::Number(seconds) if seconds.is_f64() => seconds
+            .as_f64()
+            .filter(|seconds| *seconds >= 0.0)
+            .map(|seconds| Some(seconds as u64))
+            .ok_or_else(|| invalid(value, "a timestamp")),
+        value => number(value),
+    }
+}
+
+fn quantity(value: &serde_json::Value) -> Result<Option<U256>, Error> {
+    match value {
+        serde_json::Value::Null => Ok(None),
+        value => U256::from_value(value)
+            .map(Some)
+            .ok_or_else(|| invalid(value, "a 256-bit quantity")),
+    }
 }
 
 impl FieldData {
-    pub fn add_value(&mut self, value: &serde_json::Value) {
+    pub fn add_value(&mut self, value: &serde_json::Value) -> Result<(), Error> {
         match self {
+            FieldData::Date(vec) => {
+                vec.push(
+                    timestamp(value)?
+                        .and_then(|seconds| NaiveDateTime::from_timestamp_opt(seconds as i64, 0))
+                        .map(|timestamp| timestamp.format("%Y-%m-%d").to_string()),
+                );
+                Ok(())
+            }
             FieldData::BlocksData(_data) => self.add_blocks_value(value),
             FieldData::TransactionsData(_data) => self.add_transactions_value(value),
+            FieldData::LogsData(_data) => self.add_logs_value(value),
+            FieldData::TracesData(_data) => self.add_traces_value(value),
+            FieldData::StateDiffsData(_data) => self.add_state_diffs_value(value),
             //logs
             //traces
             //_ => panic!("Unsupported type"),
         }
     }
 
-    pub fn add_blocks_value(&mut self, value: &serde_json::Value) {
+    pub fn add_blocks_value(&mut self, value: &serde_json::Value) -> Result<(), Error> {
         match self {
             Self::BlocksData(data) => match data {
-                BlockFieldData::Hash(vec) => vec.push(value.as_str().unwrap().to_string()),
-                BlockFieldData::Number(vec) => vec.push(value.as_u64().unwrap()),
-                BlockFieldData::ParentHash(vec) => vec.push(value.as_str().unwrap().to_string()),
-                BlockFieldData::Timestamp(vec) => vec.push(value.as_f64().unwrap() as u64),
-                BlockFieldData::Miner(vec) => vec.push(value.as_str().unwrap().to_string()),
-                BlockFieldData::StateRoot(vec) => vec.push(value.as_str().unwrap().to_string()),
-                BlockFieldData::TransactionsRoot(vec) => {
-                    vec.push(value.as_str().unwrap().to_string())
-                }
-                BlockFieldData::ReceiptsRoot(vec) => vec.push(value.as_str().unwrap().to_string()),
-                BlockFieldData::GasUsed(vec) => vec.push(value.as_u64().unwrap()),
-                BlockFieldData::ExtraData(vec) => vec.push(value.as_str().unwrap().to_string()),
-                BlockFieldData::BaseFeePerGas(vec) => vec.push(value.as_u64().unwrap_or(0)),
-                BlockFieldData::LogsBloom(vec) => vec.push(value.as_str().unwrap().to_string()),
-                BlockFieldData::TotalDifficulty(vec) => vec.push(value.as_u64().unwrap()),
-                BlockFieldData::Size(vec) => vec.push(value.as_u64().unwrap()),
+                BlockFieldData::Hash(vec) => vec.push(text(value)),
+                BlockFieldData::Number(vec) => vec.push(number(value)?),
+                BlockFieldData::ParentHash(vec) => vec.push(text(value)),
+                BlockFieldData::Timestamp(vec) => vec.push(timestamp(value)?),
+                BlockFieldData::Miner(vec) => vec.push(text(value)),
+                BlockFieldData::StateRoot(vec) => vec.push(text(value)),
+                BlockFieldData::TransactionsRoot(vec) => vec.push(text(value)),
+                BlockFieldData::ReceiptsRoot(vec) => vec.push(text(value)),
+                BlockFieldData::GasUsed(vec) => vec.push(number(value)?),
+                BlockFieldData::ExtraData(vec) => vec.push(text(value)),
+                BlockFieldData::BaseFeePerGas(vec) => vec.push(quantity(value)?),
+                BlockFieldData::LogsBloom(vec) => vec.push(text(value)),
+                BlockFieldData::TotalDifficulty(vec) => vec.push(quantity(value)?),
+                BlockFieldData::Size(vec) => vec.push(number(value)?),
             },
             _ => panic!("Unsupported type"),
         }
+        Ok(())
     }
-    pub fn add_transactions_value(&mut self, value: &serde_json::Value) {
+    pub fn add_transactions_value(&mut self, value: &serde_json::Value) -> Result<(), Error> {
         match self {
             Self::TransactionsData(data) => match data {
-                TransactionsFieldData::Id(vec) => vec.push(value.as_str().unwrap().to_string()),
-                TransactionsFieldData::TransactionIndex(vec) => vec.push(value.as_u64().unwrap()),
-                TransactionsFieldData::From(vec) => vec.push(value.as_str().unwrap().to_string()),
-                TransactionsFieldData::To(vec) => {
-                    vec.push(value.as_str().unwrap_or("").to_string())
-                }
-                TransactionsFieldData::Hash(vec) => vec.push(value.as_str().unwrap().to_string()),
-                TransactionsFieldData::Gas(vec) => vec.push(value.as_u64().unwrap()),
-                TransactionsFieldData::GasPrice(vec) => vec.push(value.as_u64().unwrap()),
-                TransactionsFieldData::MaxFeePerGas(vec) => vec.push(value.as_u64().unwrap_or(0)),
-                TransactionsFieldData::MaxPriorityFeePerGas(vec) => {
-                    vec.push(value.as_u64().unwrap_or(0))
-                }
-                TransactionsFieldData::Input(vec) => vec.push(value.as_str().unwrap().to_string()),
-                TransactionsFieldData::Nonce(vec) => vec.push(value.as_u64().unwrap()),
-                TransactionsFieldData::Value(vec) => {
-                    vec.push(value.as_str().unwrap_or("").to_string());
-                }
-                TransactionsFieldData::V(vec) => vec.push(value.as_u64().unwrap_or(0)),
-                TransactionsFieldData::R(vec) => vec.push(value.as_str().unwrap_or("").to_string()),
-                TransactionsFieldData::S(vec) => vec.push(value.as_str().unwrap_or("").to_string()),
-                TransactionsFieldData::YParity(vec) => vec.push(value.as_u64().unwrap_or(0)),
-                TransactionsFieldData::ChainId(vec) => vec.push(value.as_u64().unwrap_or(0)),
-                TransactionsFieldData::GasUsed(vec) => vec.push(value.as_u64().unwrap_or(0)),
-                TransactionsFieldData::CumulativeGasUsed(vec) => {
-                    vec.push(value.as_u64().unwrap_or(0))
-                }
-                TransactionsFieldData::EffectiveGasPrice(vec) => {
-                    vec.push(value.as_u64().unwrap_or(0))
-                }
-                TransactionsFieldData::ContractAddress(vec) => {
-                    vec.push(value.as_str().unwrap_or("").to_string())
-                }
-                TransactionsFieldData::Type(vec) => vec.push(value.as_u64().unwrap_or(0)),
-                TransactionsFieldData::Status(vec) => vec.push(value.as_u64().unwrap_or(0)),
-                TransactionsFieldData::Sighash(vec) => {
-                    vec.push(value.as_str().unwrap().to_string())
-                }
+                TransactionsFieldData::Id(vec) => vec.push(text(value)),
+                TransactionsFieldData::TransactionIndex(vec) => vec.push(number(value)?),
+                TransactionsFieldData::From(vec) => vec.push(text(value)),
+                TransactionsFieldData::To(vec) => vec.push(text(value)),
+                TransactionsFieldData::Hash(vec) => vec.push(text(value)),
+                TransactionsFieldData::Gas(vec) => vec.push(number(value)?),
+                TransactionsFieldData::GasPrice(vec) => vec.push(quantity(value)?),
+                TransactionsFieldData::MaxFeePerGas(vec) => vec.push(quantity(value)?),
+                TransactionsFieldData::MaxPriorityFeePerGas(vec) => vec.push(quantity(value)?),
+                TransactionsFieldData::Input(vec) => vec.push(text(value)),
+                TransactionsFieldData::Nonce(vec) => vec.push(number(value)?),
+                TransactionsFieldData::Value(vec) => vec.push(quantity(value)?),
+                TransactionsFieldData::V(vec) => vec.push(number(value)?),
+                TransactionsFieldData::R(vec) => vec.push(text(value)),
+                TransactionsFieldData::S(vec) => vec.push(text(value)),
+                TransactionsFieldData::YParity(vec) => vec.push(number(value)?),
+                TransactionsFieldData::ChainId(vec) => vec.push(number(value)?),
+                TransactionsFieldData::GasUsed(vec) => vec.push(number(value)?),
+                TransactionsFieldData::CumulativeGasUsed(vec) => vec.push(number(value)?),
+                TransactionsFieldData::EffectiveGasPrice(vec) => vec.push(quantity(value)?),
+                TransactionsFieldData::ContractAddress(vec) => vec.push(text(value)),
+                TransactionsFieldData::Type(vec) => vec.push(number(value)?),
+                TransactionsFieldData::Status(vec) => vec.push(number(value)?),
+                TransactionsFieldData::Sighash(vec) => vec.push(text(value)),
+                TransactionsFieldData::BlockNumber(vec) => vec.push(number(value)?),
             },
             _ => panic!("Unsupported type"),
         }
+        Ok(())
     }
+    pub fn add_logs_value(&mut self, value: &serde_json::Value) -> Result<(), Error> {
+        match self {
+            Self::LogsData(data) => match data {
+                LogsFieldData::BlockNumber(vec) => vec.push(number(value)?),
+                LogsFieldData::LogIndex(vec) => vec.push(number(value)?),
+                LogsFieldData::TransactionIndex(vec) => vec.push(number(value)?),
+                LogsFieldData::TransactionHash(vec) => vec.push(text(value)),
+                LogsFieldData::Address(vec) => vec.push(text(value)),
+                LogsFieldData::Data(vec) => vec.push(text(value)),
+                LogsFieldData::Topic0(vec) => vec.push(text(value)),
+                LogsFieldData::Topic1(vec) => vec.push(text(value)),
+                LogsFieldData::Topic2(vec) => vec.push(text(value)),
+                LogsFieldData::Topic3(vec) => vec.push(text(value)),
+            },
+            _ => panic!("Unsupported type"),
+        }
+        Ok(())
+    }
+    pub fn add_traces_value(&mut self, value: &serde_json::Value) -> Result<(), Error> {
+        match self {
+            Self::TracesData(data) => match data {
+                TracesFieldData::BlockNumber(vec) => vec.push(number(value)?),
+                TracesFieldData::TransactionIndex(vec) => vec.push(number(value)?),
+                TracesFieldData::TraceAddress(vec) => vec.push(match value {
+                    serde_json::Value::Null => None,
+                    serde_json::Value::Array(address) => Some(
+                        address
+                            .iter()
+                            .filter_map(text)
+                            .collect::<Vec<_>>()
+                            .join(","),
+                    ),
+                    value => return Err(invalid(value, "a trace address")),
+                }),
+                TracesFieldData::Type(vec) => vec.push(text(value)),
+                TracesFieldData::Subtraces(vec) => vec.push(number(value)?),
+                TracesFieldData::Error(vec) => vec.push(text(value)),
+                TracesFieldData::From(vec) => vec.push(text(value)),
+                TracesFieldData::To(vec) => vec.push(text(value)),
+                TracesFieldData::Value(vec) => vec.push(quantity(value)?),
+                TracesFieldData::Input(vec) => vec.push(text(value)),
+                TracesFieldData::GasUsed(vec) => vec.push(number(value)?),
+            },
+            _ => panic!("Unsupported type"),
+        }
+        Ok(())
+    }
+    pub fn add_state_diffs_value(&mut self, value: &serde_json::Value) -> Result<(), Error> {
+        match self {
+            Self::StateDiffsData(data) => match data {
+                StateDiffsFieldData::BlockNumber(vec) => vec.push(number(value)?),
+                StateDiffsFieldData::TransactionIndex(vec) => vec.push(number(value)?),
+                StateDiffsFieldData::Address(vec) => vec.push(text(value)),
+                StateDiffsFieldData::Key(vec) => vec.push(text(value)),
+                StateDiffsFieldData::Kind(vec) => vec.push(text(value)),
+                StateDiffsFieldData::Prev(vec) => vec.push(text(value)),
+                StateDiffsFieldData::Next(vec) => vec.push(text(value)),
+            },
+            _ => panic!("Unsupported type"),
+        }
+        Ok(())
+    }
+}
+
+/// Fields holding unix seconds, written as UTC timestamps unless `--raw-timestamps`
+pub fn is_timestamp(dataset: Dataset, field: &str) -> bool {
+    matches!((dataset, field), (Dataset::Blocks, "timestamp"))
+}
+
+/// Fields parsed as 256-bit quantities, see `--u256`
+pub fn is_quantity(dataset: Dataset, field: &str) -> bool {
+    matches!(
+        (dataset, field),
+        (Dataset::Blocks, "baseFeePerGas" | "totalDifficulty")
+            | (
+                Dataset::Transactions,
+                "gasPrice"
+                    | "maxFeePerGas"
+                    | "maxPriorityFeePerGas"
+                    | "value"
+                    | "effectiveGasPrice"
+            )
+            | (Dataset::Traces, "value")
+    )
+}
+
+/// Byte layout of fields holding 0x prefixed hex bytes, written as binary columns with `--binary`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HexBytes {
+    Fixed(usize),
+    Variable,
+}
+
+// quantities such as value, r and s or balance diffs drop leading zeros and stay hex text
+pub fn hex_bytes(dataset: Dataset, field: &str) -> Option<HexBytes> {
+    let bytes = match (dataset, field) {
+        (Dataset::Blocks, "hash" | "parentHash" | "stateRoot" | "transactionsRoot")
+        | (Dataset::Blocks, "receiptsRoot")
+        | (Dataset::Transactions, "hash")
+        | (Dataset::Logs, "transactionHash" | "topic0" | "topic1" | "topic2" | "topic3")
+        | (Dataset::StateDiffs, "key") => HexBytes::Fixed(32),
+        (Dataset::Blocks, "miner")
+        | (Dataset::Transactions, "from" | "to" | "contractAddress")
+        | (Dataset::Logs, "address")
+        | (Dataset::Traces, "from" | "to")
+        | (Dataset::StateDiffs, "address") => HexBytes::Fixed(20),
+        (Dataset::Blocks, "logsBloom") => HexBytes::Fixed(256),
+        (Dataset::Transactions, "sighash") => HexBytes::Fixed(4),
+        (Dataset::Blocks, "extraData")
+        | (Dataset::Transactions, "input")
+        | (Dataset::Logs, "data")
+        | (Dataset::Traces, "input") => HexBytes::Variable,
+        _ => return None,
+    };
+    Some(bytes)
 }
 
 pub fn create_field_data(field: &str, dataset: Dataset) -> FieldData {
     match dataset {
+        _ if field == DATE_FIELD => FieldData::Date(vec![]),
         Dataset::Blocks => create_block_field_data(field),
         Dataset::Transactions => create_transaction_field_data(field),
-        _ => panic!("Dataset not found"),
+        Dataset::Logs => create_log_field_data(field),
+        Dataset::Traces => create_trace_field_data(field),
+        Dataset::StateDiffs => create_state_diff_field_data(field),
     }
 }
 
@@ -176,6 +386,7 @@ fn create_transaction_field_data(field: &str) -> FieldData {
         "type" => create_transaction_field_data!(Type),
         "status" => create_transaction_field_data!(Status),
         "sighash" => create_transaction_field_data!(Sighash),
+        "blockNumber" => create_transaction_field_data!(BlockNumber),
         _ => panic!("Field '{}' not found", field),
     }
 }
@@ -205,64 +416,198 @@ pub fn create_block_field_data(field: &str) -> FieldData {
     }
 }
 
+macro_rules! create_log_field_data {
+    ($variant:ident) => {
+        FieldData::LogsData(LogsFieldData::$variant(vec![]))
+    };
+}
+
+fn create_log_field_data(field: &str) -> FieldData {
+    match field {
+        "blockNumber" => create_log_field_data!(BlockNumber),
+        "logIndex" => create_log_field_data!(LogIndex),
+        "transactionIndex" => create_log_field_data!(TransactionIndex),
+        "transactionHash" => create_log_field_data!(TransactionHash),
+        "address" => create_log_field_data!(Address),
+        "data" => create_log_field_data!(Data),
+        "topic0" => create_log_field_data!(Topic0),
+        "topic1" => create_log_field_data!(Topic1),
+        "topic2" => create_log_field_data!(Topic2),
+        "topic3" => create_log_field_data!(Topic3),
+        _ => panic!("Field '{}' not found", field),
+    }
+}
+macro_rules! create_trace_field_data {
+    ($variant:ident) => {
+        FieldData::TracesData(TracesFieldData::$variant(vec![]))
+    };
+}
+
+fn create_trace_field_data(field: &str) -> FieldData {
+    match field {
+        "blockNumber" => create_trace_field_data!(BlockNumber),
+        "transactionIndex" => create_trace_field_data!(TransactionIndex),
+        "traceAddress" => create_trace_field_data!(TraceAddress),
+        "type" => create_trace_field_data!(Type),
+        "subtraces" => create_trace_field_data!(Subtraces),
+        "error" => create_trace_field_data!(Error),
+        "from" => create_trace_field_data!(From),
+        "to" => create_trace_field_data!(To),
+        "value" => create_trace_field_data!(Value),
+        "input" => create_trace_field_data!(Input),
+        "gasUsed" => create_trace_field_data!(GasUsed),
+        _ => panic!("Field '{}' not found", field),
+    }
+}
+macro_rules! create_state_diff_field_data {
+    ($variant:ident) => {
+        FieldData::StateDiffsData(StateDiffsFieldData::$variant(vec![]))
+    };
+}
+
+fn create_state_diff_field_data(field: &str) -> FieldData {
+    match field {
+        "blockNumber" => create_state_diff_field_data!(BlockNumber),
+        "transactionIndex" => create_state_diff_field_data!(TransactionIndex),
+        "address" => create_state_diff_field_data!(Address),
+        "key" => create_state_diff_field_data!(Key),
+        "kind" => create_state_diff_field_data!(Kind),
+        "prev" => create_state_diff_field_data!(Prev),
+        "next" => create_state_diff_field_data!(Next),
+        _ => panic!("Field '{}' not found", field),
+    }
+}
+
 pub fn create_columns_from_field_data(
     field_map: &HashMap<String, FieldData>,
     fields: &[&str],
+    quantity: Quantity,
     //data: FieldData,
 ) -> Vec<Series> {
     let mut columns: Vec<Series> = vec![];
     //get dataset type here
     fields.iter().for_each(|field| match field_map.get(*field) {
+        Some(FieldData::Date(vec)) => columns.push(Series::new(field, vec)),
         Some(FieldData::BlocksData(data)) => {
             match data {
-                BlockFieldData::Hash(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::Number(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::ParentHash(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::Timestamp(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::Miner(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::StateRoot(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::TransactionsRoot(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::ReceiptsRoot(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::GasUsed(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::ExtraData(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::BaseFeePerGas(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::LogsBloom(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::TotalDifficulty(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::Size(vec) => columns.push(Series::new(*field, vec)),
+                BlockFieldData::Hash(vec) => columns.push(Series::new(field, vec)),
+                BlockFieldData::Number(vec) => columns.push(Series::new(field, vec)),
+                BlockFieldData::ParentHash(vec) => columns.push(Series::new(field, vec)),
+                BlockFieldData::Timestamp(vec) => columns.push(Series::new(field, vec)),
+                BlockFieldData::Miner(vec) => columns.push(Series::new(field, vec)),
+                BlockFieldData::StateRoot(vec) => columns.push(Series::new(field, vec)),
+                BlockFieldData::TransactionsRoot(vec) => columns.push(Series::new(field, vec)),
+                BlockFieldData::ReceiptsRoot(vec) => columns.push(Series::new(field, vec)),
+                BlockFieldData::GasUsed(vec) => columns.push(Series::new(field, vec)),
+                BlockFieldData::ExtraData(vec) => columns.push(Series::new(field, vec)),
+                BlockFieldData::BaseFeePerGas(vec) => {
+                    columns.push(quantity_series(field, vec, quantity))
+                }
+                BlockFieldData::LogsBloom(vec) => columns.push(Series::new(field, vec)),
+                BlockFieldData::TotalDifficulty(vec) => {
+                    columns.push(quantity_series(field, vec, quantity))
+                }
+                BlockFieldData::Size(vec) => columns.push(Series::new(field, vec)),
                 //_ => panic!("{} not found", field),
             };
         }
 
         Some(FieldData::TransactionsData(data)) => match data {
-            TransactionsFieldData::Id(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::TransactionIndex(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::From(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::To(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::Hash(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::Gas(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::GasPrice(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::MaxFeePerGas(vec) => columns.push(Series::new(*field, vec)),
+            TransactionsFieldData::Id(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::TransactionIndex(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::From(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::To(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::Hash(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::Gas(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::GasPrice(vec) => {
+                columns.push(quantity_series(field, vec, quantity))
+            }
+            TransactionsFieldData::MaxFeePerGas(vec) => {
+                columns.push(quantity_series(field, vec, quantity))
+            }
             TransactionsFieldData::MaxPriorityFeePerGas(vec) => {
-                columns.push(Series::new(*field, vec))
+                columns.push(quantity_series(field, vec, quantity))
             }
-            TransactionsFieldData::Input(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::Nonce(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::Value(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::V(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::R(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::S(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::YParity(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::ChainId(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::GasUsed(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::CumulativeGasUsed(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::EffectiveGasPrice(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::ContractAddress(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::Type(vec) => columns.push(Series::new(*&field, vec)),
-            TransactionsFieldData::Status(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::Sighash(vec) => columns.push(Series::new(*field, vec)),
+            TransactionsFieldData::Input(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::Nonce(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::Value(vec) => {
+                columns.push(quantity_series(field, vec, quantity))
+            }
+            TransactionsFieldData::V(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::R(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::S(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::YParity(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::ChainId(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::GasUsed(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::CumulativeGasUsed(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::EffectiveGasPrice(vec) => {
+                columns.push(quantity_series(field, vec, quantity))
+            }
+            TransactionsFieldData::ContractAddress(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::Type(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::Status(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::Sighash(vec) => columns.push(Series::new(field, vec)),
+            TransactionsFieldData::BlockNumber(vec) => columns.push(Series::new(field, vec)),
+        },
+
+        Some(FieldData::LogsData(data)) => match data {
+            LogsFieldData::BlockNumber(vec) => columns.push(Series::new(field, vec)),
+            LogsFieldData::LogIndex(vec) => columns.push(Series::new(field, vec)),
+            LogsFieldData::TransactionIndex(vec) => columns.push(Series::new(field, vec)),
+            LogsFieldData::TransactionHash(vec) => columns.push(Series::new(field, vec)),
+            LogsFieldData::Address(vec) => columns.push(Series::new(field, vec)),
+            LogsFieldData::Data(vec) => columns.push(Series::new(field, vec)),
+            LogsFieldData::Topic0(vec) => columns.push(Series::new(field, vec)),
+            LogsFieldData::Topic1(vec) => columns.push(Series::new(field, vec)),
+            LogsFieldData::Topic2(vec) => columns.push(Series::new(field, vec)),
+            LogsFieldData::Topic3(vec) => columns.push(Series::new(field, vec)),
+        },
+
+        Some(FieldData::TracesData(data)) => match data {
+            TracesFieldData::BlockNumber(vec) => columns.push(Series::new(field, vec)),
+            TracesFieldData::TransactionIndex(vec) => columns.push(Series::new(field, vec)),
+            TracesFieldData::TraceAddress(vec) => columns.push(Series::new(field, vec)),
+            TracesFieldData::Type(vec) => columns.push(Series::new(field, vec)),
+            TracesFieldData::Subtraces(vec) => columns.push(Series::new(field, vec)),
+            TracesFieldData::Error(vec) => columns.push(Series::new(field, vec)),
+            TracesFieldData::From(vec) => columns.push(Series::new(field, vec)),
+            TracesFieldData::To(vec) => columns.push(Series::new(field, vec)),
+            TracesFieldData::Value(vec) => columns.push(quantity_series(field, vec, quantity)),
+            TracesFieldData::Input(vec) => columns.push(Series::new(field, vec)),
+            TracesFieldData::GasUsed(vec) => columns.push(Series::new(field, vec)),
+        },
+
+        Some(FieldData::StateDiffsData(data)) => match data {
+            StateDiffsFieldData::BlockNumber(vec) => columns.push(Series::new(field, vec)),
+            StateDiffsFieldData::TransactionIndex(vec) => columns.push(Series::new(field, vec)),
+            StateDiffsFieldData::Address(vec) => columns.push(Series::new(field, vec)),
+            StateDiffsFieldData::Key(vec) => columns.push(Series::new(field, vec)),
+            StateDiffsFieldData::Kind(vec) => columns.push(Series::new(field, vec)),
+            StateDiffsFieldData::Prev(vec) => columns.push(Series::new(field, vec)),
+            StateDiffsFieldData::Next(vec) => columns.push(Series::new(field, vec)),
         },
 
         _ => panic!("{} not found", field),
     });
     columns
 }
+
+// decimal128 and split columns are derived from the decimal text when writing arrow data
+fn quantity_series(field: &str, values: &[Option<U256>], quantity: Quantity) -> Series {
+    match quantity {
+        Quantity::Float => Series::new(
+            field,
+            values
+                .iter()
+                .map(|value| value.map(|value| value.to_f64()))
+                .collect::<Vec<_>>(),
+        ),
+        _ => Series::new(
+            field,
+            values
+                .iter()
+                .map(|value| value.map(|value| value.to_string()))
+                .collect::<Vec<_>>(),
+        ),
+    }
+}
@@ -0,0 +1,484 @@
+use crate::export::u256::U256;
+use anyhow::{anyhow, Result};
+use polars::prelude::{BooleanChunked, DataFrame, DataType, NewChunkedArray, Series};
+use std::cmp::Ordering;
+
+/// Row filter given with `--where`, e.g. `value > 1e18 && status == 1`.
+/// Numbers compare numerically, exactly for integers, hex strings in numeric
+/// comparisons are read as quantities and quoted strings compare as text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowFilter {
+    expression: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Op, Operand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Column(String),
+    Number(Number),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(Number),
+    Text(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+// integers up to 256 bits compare exactly, wei values exceed the 2^53 of f64
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Number {
+    Int(U256),
+    Float(f64),
+}
+
+// one row of a column
+#[derive(Debug, Clone, PartialEq)]
+enum Cell {
+    Num(Number),
+    Str(String),
+    Null,
+}
+
+impl RowFilter {
+    pub fn parse(input: &str) -> Result<Self> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let expression = parser.or()?;
+        if parser.position != parser.tokens.len() {
+            return Err(anyhow!(
+                "Invalid filter: unexpected {:?} in `{}`",
+                parser.tokens[parser.position],
+                input
+            ));
+        }
+        Ok(RowFilter { expression })
+    }
+
+    /// Columns the expression reads, to check them against the selected fields
+    pub fn columns(&self) -> Vec<&str> {
+        let mut columns = Vec::new();
+        self.expression.columns(&mut columns);
+        columns
+    }
+
+    pub fn apply(&self, df: &DataFrame) -> Result<DataFrame> {
+        let mask = self.expression.evaluate(df)?;
+        Ok(df.filter(&BooleanChunked::from_slice("mask", &mask))?)
+    }
+}
+
+impl Expr {
+    fn columns<'a>(&'a self, columns: &mut Vec<&'a str>) {
+        match self {
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                left.columns(columns);
+                right.columns(columns);
+            }
+            Expr::Not(inner) => inner.columns(columns),
+            Expr::Compare(left, _, right) => {
+                for operand in [left, right] {
+                    if let Operand::Column(name) = operand {
+                        if !columns.contains(&name.as_str()) {
+                            columns.push(name);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn evaluate(&self, df: &DataFrame) -> Result<Vec<bool>> {
+        match self {
+            Expr::And(left, right) => {
+                let (left, right) = (left.evaluate(df)?, right.evaluate(df)?);
+                Ok(left.iter().zip(right).map(|(l, r)| *l && r).collect())
+            }
+            Expr::Or(left, right) => {
+                let (left, right) = (left.evaluate(df)?, right.evaluate(df)?);
+                Ok(left.iter().zip(right).map(|(l, r)| *l || r).collect())
+            }
+            Expr::Not(inner) => Ok(inner.evaluate(df)?.into_iter().map(|v| !v).collect()),
+            Expr::Compare(left, op, right) => {
+                let left = operand_cells(left, df)?;
+                let right = operand_cells(right, df)?;
+                Ok((0..df.height())
+                    .map(|row| compare(cell(&left, row), *op, cell(&right, row)))
+                    .collect())
+            }
+        }
+    }
+}
+
+// literals are a single cell repeated for every row
+fn cell(cells: &[Cell], row: usize) -> &Cell {
+    if cells.len() == 1 {
+        &cells[0]
+    } else {
+        &cells[row]
+    }
+}
+
+fn operand_cells(operand: &Operand, df: &DataFrame) -> Result<Vec<Cell>> {
+    match operand {
+        Operand::Number(number) => Ok(vec![Cell::Num(*number)]),
+        Operand::Text(text) => Ok(vec![Cell::Str(text.clone())]),
+        Operand::Column(name) => series_cells(df.column(name)?),
+    }
+}
+
+fn series_cells(series: &Series) -> Result<Vec<Cell>> {
+    match series.dtype() {
+        DataType::Utf8 => Ok(series
+            .utf8()?
+            .into_iter()
+            .map(|value| value.map_or(Cell::Null, |value| Cell::Str(value.to_owned())))
+            .collect()),
+        DataType::Boolean => Ok(series
+            .bool()?
+            .into_iter()
+            .map(|value| {
+                value.map_or(Cell::Null, |value| {
+                    Cell::Num(Number::Int(U256::from(value as u64)))
+                })
+            })
+            .collect()),
+        DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => Ok(series
+            .cast(&DataType::UInt64)?
+            .u64()?
+            .into_iter()
+            .map(|value| value.map_or(Cell::Null, |value| Cell::Num(Number::Int(value.into()))))
+            .collect()),
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => Ok(series
+            .cast(&DataType::Int64)?
+            .i64()?
+            .into_iter()
+            .map(|value| match value {
+                Some(value) if value >= 0 => Cell::Num(Number::Int(U256::from(value as u64))),
+                Some(value) => Cell::Num(Number::Float(value as f64)),
+                None => Cell::Null,
+            })
+            .collect()),
+        _ => Ok(series
+            .cast(&DataType::Float64)?
+            .f64()?
+            .into_iter()
+            .map(|value| value.map_or(Cell::Null, |value| Cell::Num(Number::Float(value))))
+            .collect()),
+    }
+}
+
+fn compare(left: &Cell, op: Op, right: &Cell) -> bool {
+    let ordering = match (left, right) {
+        (Cell::Null, _) | (_, Cell::Null) => return false,
+        (Cell::Num(l), Cell::Num(r)) => compare_numbers(*l, *r),
+        (Cell::Num(l), Cell::Str(r)) => parse_number(r).and_then(|r| compare_numbers(*l, r)),
+        (Cell::Str(l), Cell::Num(r)) => parse_number(l).and_then(|l| compare_numbers(l, *r)),
+        (Cell::Str(l), Cell::Str(r)) => {
+            let (l, r) = (l.to_lowercase(), r.to_lowercase());
+            match (op, l.starts_with("0x"), r.starts_with("0x")) {
+                // hex quantities of different length only order numerically
+                (Op::Gt | Op::Ge | Op::Lt | Op::Le, true, true) => parse_number(&l)
+                    .and_then(|l| parse_number(&r).and_then(|r| compare_numbers(l, r))),
+                // decimal quantities against hex literals
+                (_, true, false) | (_, false, true) => match (parse_number(&l), parse_number(&r)) {
+                    (Some(l), Some(r)) => compare_numbers(l, r),
+                    _ => Some(l.cmp(&r)),
+                },
+                _ => Some(l.cmp(&r)),
+            }
+        }
+    };
+    match ordering {
+        Some(ordering) => match op {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ne => ordering != Ordering::Equal,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+        },
+        None => false,
+    }
+}
+
+// floats without a fraction, such as 1e18, are integers and compare exactly too
+fn compare_numbers(left: Number, right: Number) -> Option<Ordering> {
+    let exact = |number| match number {
+        Number::Int(int) => Some(int),
+        Number::Float(float) => U256::from_f64(float),
+    };
+    match (exact(left), exact(right)) {
+        (Some(l), Some(r)) => Some(l.cmp(&r)),
+        _ => to_f64(left).partial_cmp(&to_f64(right)),
+    }
+}
+
+fn to_f64(number: Number) -> f64 {
+    match number {
+        Number::Int(int) => int.to_f64(),
+        Number::Float(float) => float,
+    }
+}
+
+// 0x prefixed hex and decimal digits are integers, anything else with a fraction or
+// exponent a float
+fn parse_number(value: &str) -> Option<Number> {
+    let value = value.replace('_', "");
+    if value.starts_with("0x") || value.chars().all(|c| c.is_ascii_digit()) {
+        return U256::parse(&value).map(Number::Int);
+    }
+    value.parse::<f64>().ok().map(Number::Float)
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let (token, length) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('!', _) => (Token::Not, 1),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('\'' | '"', _) => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&q| q == c)
+                    .ok_or_else(|| anyhow!("Invalid filter: unterminated string in `{}`", input))?;
+                let text: String = chars[i + 1..i + 1 + end].iter().collect();
+                (Token::Text(text), end + 2)
+            }
+            (c, _) if c.is_ascii_alphanumeric() || c == '_' || c == '.' => {
+                let length = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == '.')
+                    .count();
+                let word: String = chars[i..i + length].iter().collect();
+                // 1e18 and 1.5e-3 carry a sign after the exponent
+                let (word, length) = match (word.ends_with(['e', 'E']), chars.get(i + length)) {
+                    (true, Some(sign @ ('+' | '-'))) if c.is_ascii_digit() => {
+                        let digits = chars[i + length + 1..]
+                            .iter()
+                            .take_while(|c| c.is_ascii_digit())
+                            .count();
+                        let exponent: String = chars[i + length + 1..i + length + 1 + digits]
+                            .iter()
+                            .collect();
+                        (format!("{}{}{}", word, sign, exponent), length + 1 + digits)
+                    }
+                    _ => (word, length),
+                };
+                let token = if word.starts_with("0x") {
+                    Token::Text(word)
+                } else if c.is_ascii_digit() || c == '.' {
+                    let number = parse_number(&word)
+                        .ok_or_else(|| anyhow!("Invalid filter: bad number {}", word))?;
+                    Token::Number(number)
+                } else {
+                    Token::Ident(word)
+                };
+                (token, length)
+            }
+            (c, _) => return Err(anyhow!("Invalid filter: unexpected `{}` in `{}`", c, input)),
+        };
+        tokens.push(token);
+        i += length;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expression = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            expression = Expr::Or(Box::new(expression), Box::new(self.and()?));
+        }
+        Ok(expression)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expression = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            expression = Expr::And(Box::new(expression), Box::new(self.not()?));
+        }
+        Ok(expression)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.position += 1;
+                Ok(Expr::Not(Box::new(self.not()?)))
+            }
+            Some(Token::Open) => {
+                self.position += 1;
+                let expression = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expression),
+                    _ => Err(anyhow!("Invalid filter: missing `)`")),
+                }
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let left = self.operand()?;
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            token => {
+                return Err(anyhow!(
+                    "Invalid filter: expected a comparison, got {:?}",
+                    token
+                ))
+            }
+        };
+        let right = self.operand()?;
+        Ok(Expr::Compare(left, op, right))
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(Operand::Column(name)),
+            Some(Token::Number(number)) => Ok(Operand::Number(number)),
+            Some(Token::Text(text)) => Ok(Operand::Text(text)),
+            token => Err(anyhow!(
+                "Invalid filter: expected a column or value, got {:?}",
+                token
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RowFilter;
+    use anyhow::Result;
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    #[test]
+    fn test_filter() -> Result<()> {
+        let df = DataFrame::new(vec![
+            Series::new("gasUsed", &[21000u64, 15000000, 30000000]),
+            Series::new("status", &[1u64, 0, 1]),
+            Series::new("value", &["0x0", "0xde0b6b3a7640001", "0x1bc16d674ec80000"]),
+            Series::new("to", &["0xAB", "0xcd", "0xab"]),
+        ])?;
+
+        let filter = RowFilter::parse("value > 1e18 && status == 1")?;
+        assert_eq!(filter.columns(), vec!["value", "status"]);
+        assert_eq!(filter.apply(&df)?.height(), 1);
+
+        let filter = RowFilter::parse("gasUsed > 10000000 || !(to != 0xab)")?;
+        assert_eq!(filter.apply(&df)?.height(), 3);
+
+        let filter = RowFilter::parse("to == 'something else'")?;
+        assert_eq!(filter.apply(&df)?.height(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exact_quantities() -> Result<()> {
+        let df = DataFrame::new(vec![
+            Series::new(
+                "value",
+                &[
+                    "1000000000000000000",
+                    "1000000000000000001",
+                    "0xde0b6b3a7640001",
+                ],
+            ),
+            Series::new("gas", &[9007199254740993u64, 9007199254740992, 1]),
+        ])?;
+        // neighbours above 2^53 are one f64 apart
+        for (input, rows) in [
+            ("value == 1000000000000000001", 2),
+            ("value > 1e18", 2),
+            ("value >= 1e18", 3),
+            ("value > 0xde0b6b3a7640000", 2),
+            ("value < 0xde0b6b3a7640001", 1),
+            ("gas == 9007199254740993", 1),
+            ("gas > 9007199254740992", 1),
+            ("gas > 0.5", 3),
+        ] {
+            let filter = RowFilter::parse(input)?;
+            assert_eq!(filter.apply(&df)?.height(), rows, "{}", input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_filter() {
+        for input in [
+            "value >",
+            "value 1",
+            "(status == 1",
+            "status == 'x",
+            "a = 1",
+        ] {
+            assert!(
+                RowFilter::parse(input).is_err(),
+                "{} should be rejected",
+                input
+            );
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::cli::config::{Dataset, OutputConfig};
+use crate::export::parquet;
+use crate::export::sqlite;
+use chrono::{SecondsFormat, Utc};
+use polars::export::arrow::io::ipc::read::{read_file_metadata, read_stream_metadata};
+use serde_json::{Map, Value};
+use std::fs::{self, File};
+use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+
+/// Run metadata of a file: the query that produced it, the span its name covers and
+/// the first and last block it holds, absent when the span had no matching blocks
+pub fn file_metadata(
+    dataset: Dataset,
+    fields: &[String],
+    start: u64,
+    end: u64,
+    blocks: &[Value],
+    output: &OutputConfig,
+) -> Vec<(String, String)> {
+    let mut metadata = vec![
+        ("dataset".to_owned(), dataset.name().to_owned()),
+        ("range".to_owned(), format!("{}:{}", start, end)),
+    ];
+    let numbers = blocks
+        .iter()
+        .filter_map(|block| block["header"]["number"].as_u64());
+    if let (Some(first), Some(last)) = (numbers.clone().min(), numbers.max()) {
+        metadata.push(("first_block".to_owned(), first.to_string()));
+        metadata.push(("last_block".to_owned(), last.to_string()));
+    }
+    metadata.push(("fields".to_owned(), fields.join(",")));
+    metadata.extend(output.metadata.iter().cloned());
+    metadata.push((
+        "fetched_at".to_owned(),
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+    ));
+    metadata
+}
+
+/// Metadata of a database table after another chunk, spans and blocks widened to
+/// cover both, everything else taken from the chunk and kept when it has none
+pub fn merge_metadata(
+    existing: &[(String, String)],
+    chunk: Vec<(String, String)>,
+) -> Vec<(String, String)> {
+    let find = |key: &str| {
+        existing
+            .iter()
+            .find(|(existing_key, _)| existing_key == key)
+            .map(|(_, value)| value.as_str())
+    };
+    let bounds = |value: &str| -> Option<(u64, u64)> {
+        let (start, end) = value.split_once(':').unwrap_or((value, value));
+        Some((start.parse().ok()?, end.parse().ok()?))
+    };
+    let mut merged: Vec<(String, String)> = chunk
+        .into_iter()
+        .map(|(key, value)| {
+            let merged =
+                match (key.as_str(), find(&key)) {
+                    ("range", Some(old)) => bounds(old).zip(bounds(&value)).map(
+                        |((start, end), (new_start, new_end))| {
+                            format!("{}:{}", start.min(new_start), end.max(new_end))
+                        },
+                    ),
+                    ("first_block", Some(old)) => bounds(old)
+                        .zip(bounds(&value))
+                        .map(|((old, _), (new, _))| old.min(new).to_string()),
+                    ("last_block", Some(old)) => bounds(old)
+                        .zip(bounds(&value))
+                        .map(|((old, _), (new, _))| old.max(new).to_string()),
+                    _ => None,
+                };
+            (key, merged.unwrap_or(value))
+        })
+        .collect();
+    for (key, value) in existing {
+        if !merged.iter().any(|(merged_key, _)| merged_key == key) {
+            merged.push((key.clone(), value.clone()));
+        }
+    }
+    merged
+}
+
+// hidden, so readers globbing the output directory skip it
+fn sidecar_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.meta.json", name))
+}
+
+/// Writes the metadata of a csv or json file, which have no place for it, beside it
+pub fn write_sidecar(path: &Path, metadata: &[(String, String)]) -> Result<(), Error> {
+    let object: Map<String, Value> = metadata
+        .iter()
+        .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+        .collect();
+    let mut file = File::create(sidecar_path(path))?;
+    file.write_all(serde_json::to_string_pretty(&object)?.as_bytes())?;
+    file.sync_all()
+}
+
+/// Metadata of an output file, from the parquet footer, the arrow schema, the database
+/// or the sidecar of csv and json files
+pub fn read_metadata(path: &Path) -> Result<Vec<(String, String)>, Error> {
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    match extension.as_ref() {
+        "parquet" => parquet::read_metadata(path),
+        "arrow" => {
+            let metadata = read_file_metadata(&mut File::open(path)?).map_err(Error::other)?;
+            Ok(metadata.schema.metadata.into_iter().collect())
+        }
+        "arrows" => {
+            let metadata = read_stream_metadata(&mut File::open(path)?).map_err(Error::other)?;
+            Ok(metadata.schema.metadata.into_iter().collect())
+        }
+        "db" => sqlite::read_metadata(path),
+        #[cfg(feature = "duckdb")]
+        "duckdb" => crate::export::duckdb::read_metadata(path),
+        _ => {
+            let sidecar = sidecar_path(path);
+            if !sidecar.exists() {
+                return Err(Error::other(format!(
+                    "no metadata, {} is missing",
+                    sidecar.display()
+                )));
+            }
+            let object: Map<String, Value> = serde_json::from_str(&fs::read_to_string(sidecar)?)?;
+            Ok(object
+                .into_iter()
+                .map(|(key, value)| match value {
+                    Value::String(value) => (key, value),
+                    value => (key, value.to_string()),
+                })
+                .collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{file_metadata, merge_metadata, read_metadata, write_sidecar};
+    use crate::cli::config::{test_opts, Config, Dataset};
+    use serde_json::json;
+    use std::fs;
+
+    fn pairs(metadata: &[(String, String)]) -> Vec<(&str, &str)> {
+        metadata
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_file_metadata() -> anyhow::Result<()> {
+        let config: Config = test_opts("blocks", "0:99").try_into()?;
+        let blocks = [
+            json!({"header": {"number": 12}}),
+            json!({"header": {"number": 40}}),
+        ];
+        let fields = ["number".to_owned()];
+        let metadata = file_metadata(Dataset::Blocks, &fields, 0, 49, &blocks, &config.output);
+        assert_eq!(
+            pairs(&metadata[..5]),
+            [
+                ("dataset", "blocks"),
+                ("range", "0:49"),
+                ("first_block", "12"),
+                ("last_block", "40"),
+                ("fields", "number"),
+            ]
+        );
+        // a span without blocks has no first and last block
+        let empty = file_metadata(Dataset::Blocks, &fields, 50, 99, &[], &config.output);
+        assert!(empty.iter().all(|(key, _)| key != "first_block"));
+
+        // csv and json keep theirs beside the file
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("ethereum__blocks__0_to_49.csv");
+        fs::write(&path, "")?;
+        write_sidecar(&path, &metadata)?;
+        assert!(temp
+            .path()
+            .join(".ethereum__blocks__0_to_49.csv.meta.json")
+            .exists());
+        assert_eq!(read_metadata(&path)?, metadata);
+
+        // database tables cover every chunk written to them
+        let merged = merge_metadata(
+            &metadata,
+            file_metadata(
+                Dataset::Blocks,
+                &fields,
+                50,
+                99,
+                &[blocks[0].clone()],
+                &config.output,
+            ),
+        );
+        let kept = merge_metadata(&merged, empty);
+        assert_eq!(
+            pairs(&merged[..4]),
+            [
+                ("dataset", "blocks"),
+                ("range", "0:99"),
+                ("first_block", "12"),
+                ("last_block", "40"),
+            ]
+        );
+        assert!(kept.contains(&("first_block".to_owned(), "12".to_owned())));
+        assert!(kept.contains(&("range".to_owned(), "0:99".to_owned())));
+        Ok(())
+    }
+}
@@ -1,2 +1,13 @@
+pub mod columns;
+pub mod database;
+#[cfg(feature = "duckdb")]
+pub mod duckdb;
+pub mod existing;
+#[allow(clippy::module_inception)]
 pub mod export;
 pub mod fields;
+pub mod filter;
+pub mod metadata;
+pub mod parquet;
+pub mod sqlite;
+pub mod u256;
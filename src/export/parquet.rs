@@ -0,0 +1,271 @@
+use crate::cli::config::{ColumnTypes, Dataset, ParquetOptions};
+use crate::export::columns::arrow_columns;
+use polars::export::arrow::array::Array;
+use polars::export::arrow::chunk::Chunk;
+use polars::export::arrow::compute::cast::{cast, CastOptions};
+use polars::export::arrow::datatypes::{
+    DataType as ArrowDataType, Field, IntegerType, Schema, TimeUnit,
+};
+use polars::export::arrow::io::parquet::read;
+use polars::export::arrow::io::parquet::write::{
+    transverse, Encoding, FileWriter, KeyValue, RowGroupIterator, Version, WriteOptions,
+};
+use polars::prelude::DataFrame;
+use std::fs::File;
+use std::io::{Error, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const ARROW_SCHEMA_KEY: &str = "ARROW:schema";
+
+/// Writes `df` as a parquet file, split into row groups of `options.row_group_size` rows,
+/// with `metadata` in the key-value metadata of the footer
+pub fn write_parquet<W: Write>(
+    df: &mut DataFrame,
+    writer: W,
+    options: &ParquetOptions,
+    dataset: Dataset,
+    columns: ColumnTypes,
+    metadata: Vec<(String, String)>,
+) -> Result<(), Error> {
+    let (schema, columns) = arrow_columns(df, dataset, columns)?;
+    let (schema, columns) = millisecond_timestamps(schema, columns)?;
+    let write_options = WriteOptions {
+        write_statistics: options.statistics,
+        compression: options.compression,
+        version: Version::V2,
+    };
+    let encodings: Vec<Vec<Encoding>> = schema
+        .fields
+        .iter()
+        .map(|field| {
+            transverse(&field.data_type, |data_type| {
+                if options.dictionary && is_text(data_type) {
+                    Encoding::RleDictionary
+                } else {
+                    Encoding::Plain
+                }
+            })
+        })
+        .collect();
+
+    let height = df.height();
+    let group_size = options.row_group_size.unwrap_or(height).max(1);
+    // an empty chunk still gets a row group so the file carries its schema
+    let groups = (0..height.max(1)).step_by(group_size).map(|offset| {
+        let length = group_size.min(height - offset);
+        let arrays = columns
+            .iter()
+            .map(|column| {
+                let array = column.slice(offset, length);
+                if options.dictionary && is_text(array.data_type()) {
+                    dictionary_array(array.as_ref()).map(Arc::from)
+                } else {
+                    Ok(Arc::from(array))
+                }
+            })
+            .collect::<polars::export::arrow::error::Result<Vec<_>>>()?;
+        Ok(Chunk::new(arrays))
+    });
+    let row_groups = RowGroupIterator::try_new(groups, &schema, write_options, encodings)
+        .map_err(Error::other)?;
+
+    let mut file = FileWriter::try_new(writer, schema, write_options).map_err(Error::other)?;
+    for group in row_groups {
+        file.write(group.map_err(Error::other)?)
+            .map_err(Error::other)?;
+    }
+    let metadata = metadata
+        .into_iter()
+        .map(|(key, value)| KeyValue {
+            key,
+            value: Some(value),
+        })
+        .collect();
+    file.end(Some(metadata)).map_err(Error::other)?;
+    Ok(())
+}
+
+/// Key-value metadata of the parquet file at `path`, along with its row and row group counts
+pub fn read_metadata(path: &Path) -> Result<Vec<(String, String)>, Error> {
+    let mut file = File::open(path)?;
+    let metadata = read::read_metadata(&mut file).map_err(Error::other)?;
+    let mut values = vec![
+        ("rows".to_owned(), metadata.num_rows.to_string()),
+        (
+            "row_groups".to_owned(),
+            metadata.row_groups.len().to_string(),
+        ),
+    ];
+    for KeyValue { key, value } in metadata.key_value_metadata.unwrap_or_default() {
+        // the serialized arrow schema is of no use to a reader
+        if key != ARROW_SCHEMA_KEY {
+            values.push((key, value.unwrap_or_default()));
+        }
+    }
+    Ok(values)
+}
+
+// parquet has no seconds unit, arrow2 would write second timestamps as plain int64
+fn millisecond_timestamps(
+    schema: Schema,
+    columns: Vec<Arc<dyn Array>>,
+) -> Result<(Schema, Vec<Arc<dyn Array>>), Error> {
+    let mut fields = Vec::new();
+    let mut arrays = Vec::new();
+    for (field, array) in schema.fields.into_iter().zip(columns) {
+        match field.data_type {
+            ArrowDataType::Timestamp(TimeUnit::Second, timezone) => {
+                let data_type = ArrowDataType::Timestamp(TimeUnit::Millisecond, timezone);
+                let array = cast(array.as_ref(), &data_type, CastOptions::default())
+                    .map_err(Error::other)?;
+                fields.push(Field::new(field.name, data_type, field.is_nullable));
+                arrays.push(Arc::from(array));
+            }
+            _ => {
+                fields.push(field);
+                arrays.push(array);
+            }
+        }
+    }
+    Ok((Schema::from(fields), arrays))
+}
+
+fn is_text(data_type: &ArrowDataType) -> bool {
+    matches!(data_type, ArrowDataType::Utf8 | ArrowDataType::LargeUtf8)
+}
+
+// arrow2 only dictionary encodes dictionary arrays, the file schema keeps the plain text type
+fn dictionary_array(array: &dyn Array) -> polars::export::arrow::error::Result<Box<dyn Array>> {
+    let data_type = ArrowDataType::Dictionary(
+        IntegerType::UInt32,
+        Box::new(array.data_type().clone()),
+        false,
+    );
+    cast(array, &data_type, CastOptions::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_metadata, write_parquet};
+    use crate::cli::config::{ColumnTypes, Dataset, ParquetOptions};
+    use polars::export::arrow::io::parquet::write::{CompressionOptions, ZstdLevel};
+    use polars::prelude::{
+        DataFrame, DataType, NamedFrom, ParquetReader, SerReader, Series, TimeUnit,
+    };
+    use std::fs::File;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write_parquet() {
+        let numbers: Vec<u64> = (0..25).collect();
+        let senders: Vec<String> = numbers.iter().map(|n| format!("0x{:x}", n % 3)).collect();
+        let mut df = DataFrame::new(vec![
+            Series::new("number", &numbers),
+            Series::new("from", &senders),
+        ])
+        .unwrap();
+
+        for compression in [
+            CompressionOptions::Snappy,
+            CompressionOptions::Zstd(Some(ZstdLevel::try_new(19).unwrap())),
+            CompressionOptions::Lz4Raw,
+            CompressionOptions::Gzip(None),
+            CompressionOptions::Uncompressed,
+        ] {
+            let options = ParquetOptions {
+                compression,
+                row_group_size: Some(10),
+                dictionary: true,
+                statistics: true,
+            };
+            let mut buffer = Vec::new();
+            write_parquet(
+                &mut df,
+                &mut buffer,
+                &options,
+                Dataset::Transactions,
+                ColumnTypes::default(),
+                Vec::new(),
+            )
+            .unwrap();
+            let read = ParquetReader::new(Cursor::new(buffer)).finish().unwrap();
+            assert!(read.frame_equal(&df));
+        }
+    }
+
+    #[test]
+    fn test_metadata() {
+        let mut df = DataFrame::new(vec![Series::new("number", &[1u64, 2, 3])]).unwrap();
+        let options = ParquetOptions {
+            compression: CompressionOptions::Snappy,
+            row_group_size: Some(2),
+            dictionary: false,
+            statistics: true,
+        };
+        let metadata = vec![
+            ("dataset".to_owned(), "blocks".to_owned()),
+            ("range".to_owned(), "1:3".to_owned()),
+        ];
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("metadata.parquet");
+        write_parquet(
+            &mut df,
+            File::create(&path).unwrap(),
+            &options,
+            Dataset::Blocks,
+            ColumnTypes::default(),
+            metadata,
+        )
+        .unwrap();
+
+        let read = read_metadata(&path).unwrap();
+        let read: Vec<(&str, &str)> = read.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            read,
+            [
+                ("rows", "3"),
+                ("row_groups", "2"),
+                ("dataset", "blocks"),
+                ("range", "1:3")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let mut df = DataFrame::new(vec![Series::new("timestamp", &[1690000000u64])]).unwrap();
+        let options = ParquetOptions {
+            compression: CompressionOptions::Snappy,
+            row_group_size: None,
+            dictionary: false,
+            statistics: true,
+        };
+        let mut buffer = Vec::new();
+        write_parquet(
+            &mut df,
+            &mut buffer,
+            &options,
+            Dataset::Blocks,
+            ColumnTypes::default(),
+            Vec::new(),
+        )
+        .unwrap();
+        let read = ParquetReader::new(Cursor::new(buffer)).finish().unwrap();
+        let timestamp = read.column("timestamp").unwrap();
+        assert_eq!(
+            timestamp.dtype(),
+            &DataType::Datetime(TimeUnit::Milliseconds, Some("UTC".to_owned()))
+        );
+        assert_eq!(
+            timestamp
+                .cast(&DataType::Int64)
+                .unwrap()
+                .i64()
+                .unwrap()
+                .into_iter()
+                .next(),
+            Some(Some(1690000000000))
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use crate::cli::config::Dataset;
+use crate::export::database::{DatabaseWriter, METADATA_TABLE};
+use crate::export::metadata::merge_metadata;
+use polars::prelude::{AnyValue, DataFrame, DataType};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags};
+use std::collections::HashSet;
+use std::io::Error;
+use std::path::Path;
+
+// columns worth an index when selected
+const INDEXED_COLUMNS: [&str; 7] = [
+    "number",
+    "blockNumber",
+    "hash",
+    "transactionHash",
+    "address",
+    "from",
+    "to",
+];
+
+/// Columns identifying a row of `dataset`, re-runs over the same blocks replace
+/// rows with the same key instead of adding duplicates
+pub fn table_key(dataset: Dataset, fields: &[String]) -> Option<&'static [&'static str]> {
+    let candidates: &[&[&str]] = match dataset {
+        Dataset::Blocks => &[&["number"], &["hash"]],
+        Dataset::Transactions => &[&["hash"], &["blockNumber", "transactionIndex"]],
+        Dataset::Logs => &[
+            &["blockNumber", "logIndex"],
+            &["transactionHash", "logIndex"],
+        ],
+        Dataset::Traces => &[&["blockNumber", "transactionIndex", "traceAddress"]],
+        Dataset::StateDiffs => &[&["blockNumber", "transactionIndex", "address", "key"]],
+    };
+    candidates
+        .iter()
+        .find(|key| key.iter().all(|column| fields.iter().any(|f| f == column)))
+        .copied()
+}
+
+/// Single file database with a table per dataset, every chunk is written in one transaction
+pub struct SqliteWriter {
+    connection: Connection,
+    tables: HashSet<&'static str>,
+}
+
+impl SqliteWriter {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let connection = Connection::open(path).map_err(Error::other)?;
+        Ok(SqliteWriter {
+            connection,
+            tables: HashSet::new(),
+        })
+    }
+}
+
+impl DatabaseWriter for SqliteWriter {
+    fn write_chunk(&mut self, frames: &[(Dataset, DataFrame)]) -> Result<(), Error> {
+        for (dataset, df) in frames {
+            if self.tables.insert(dataset.name()) {
+                create_table(&self.connection, *dataset, df).map_err(Error::other)?;
+            }
+        }
+        let transaction = self.connection.transaction().map_err(Error::other)?;
+        for (dataset, df) in frames {
+            insert_rows(&transaction, *dataset, df).map_err(Error::other)?;
+        }
+        transaction.commit().map_err(Error::other)
+    }
+
+    fn write_metadata(
+        &mut self,
+        dataset: Dataset,
+        metadata: Vec<(String, String)>,
+    ) -> Result<(), Error> {
+        self.connection
+            .execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {} \
+                 (dataset TEXT NOT NULL, key TEXT NOT NULL, value TEXT, PRIMARY KEY (dataset, key));",
+                METADATA_TABLE
+            ))
+            .map_err(Error::other)?;
+        let existing = dataset_metadata(&self.connection, dataset.name()).map_err(Error::other)?;
+        let transaction = self.connection.transaction().map_err(Error::other)?;
+        transaction
+            .execute(
+                &format!("DELETE FROM {} WHERE dataset = ?1", METADATA_TABLE),
+                [dataset.name()],
+            )
+            .map_err(Error::other)?;
+        for (key, value) in merge_metadata(&existing, metadata) {
+            transaction
+                .execute(
+                    &format!("INSERT INTO {} VALUES (?1, ?2, ?3)", METADATA_TABLE),
+                    params![dataset.name(), key, value],
+                )
+                .map_err(Error::other)?;
+        }
+        transaction.commit().map_err(Error::other)
+    }
+}
+
+// in the order they were written
+fn dataset_metadata(
+    connection: &Connection,
+    dataset: &str,
+) -> rusqlite::Result<Vec<(String, String)>> {
+    let mut statement = connection.prepare(&format!(
+        "SELECT key, value FROM {} WHERE dataset = ?1 ORDER BY rowid",
+        METADATA_TABLE
+    ))?;
+    let rows = statement.query_map([dataset], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+/// Run metadata of every dataset in the sqlite database at `path`
+pub fn read_metadata(path: &Path) -> Result<Vec<(String, String)>, Error> {
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(Error::other)?;
+    let mut statement = connection
+        .prepare(&format!(
+            "SELECT key, value FROM {} ORDER BY dataset, rowid",
+            METADATA_TABLE
+        ))
+        .map_err(Error::other)?;
+    let rows = statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(Error::other)?;
+    rows.collect::<rusqlite::Result<_>>().map_err(Error::other)
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn column_type(dtype: &DataType) -> &'static str {
+    match dtype {
+        DataType::Boolean
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64 => "INTEGER",
+        DataType::Float32 | DataType::Float64 => "REAL",
+        _ => "TEXT",
+    }
+}
+
+// tables of earlier runs get the newly selected columns added
+fn create_table(connection: &Connection, dataset: Dataset, df: &DataFrame) -> rusqlite::Result<()> {
+    let table = dataset.name();
+    let columns: Vec<String> = df
+        .get_columns()
+        .iter()
+        .map(|series| format!("{} {}", quote(series.name()), column_type(series.dtype())))
+        .collect();
+    connection.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {} ({});",
+        quote(table),
+        columns.join(", ")
+    ))?;
+
+    let mut statement = connection.prepare(&format!("PRAGMA table_info({})", quote(table)))?;
+    let existing = statement
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<HashSet<String>>>()?;
+    for series in df.get_columns() {
+        if !existing.contains(series.name()) {
+            connection.execute_batch(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {};",
+                quote(table),
+                quote(series.name()),
+                column_type(series.dtype())
+            ))?;
+        }
+    }
+
+    let fields: Vec<String> = df.get_column_names_owned();
+    if let Some(key) = table_key(dataset, &fields) {
+        let columns: Vec<String> = key.iter().map(|column| quote(column)).collect();
+        connection.execute_batch(&format!(
+            "CREATE UNIQUE INDEX IF NOT EXISTS {} ON {} ({});",
+            quote(&format!("{}_key", table)),
+            quote(table),
+            columns.join(", ")
+        ))?;
+    }
+    for column in INDEXED_COLUMNS {
+        if fields.iter().any(|field| field == column) {
+            connection.execute_batch(&format!(
+                "CREATE INDEX IF NOT EXISTS {} ON {} ({});",
+                quote(&format!("{}_{}", table, column)),
+                quote(table),
+                quote(column)
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+fn insert_rows(connection: &Connection, dataset: Dataset, df: &DataFrame) -> rusqlite::Result<()> {
+    let columns: Vec<String> = df
+        .get_column_names()
+        .iter()
+        .map(|column| quote(column))
+        .collect();
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{}", i)).collect();
+    let mut statement = connection.prepare_cached(&format!(
+        "INSERT OR REPLACE INTO {} ({}) VALUES ({})",
+        quote(dataset.name()),
+        columns.join(", "),
+        placeholders.join(", ")
+    ))?;
+    for row in 0..df.height() {
+        let values = df
+            .get_columns()
+            .iter()
+            .map(|series| sql_value(series.get(row)));
+        statement.execute(params_from_iter(values))?;
+    }
+    Ok(())
+}
+
+fn sql_value(value: AnyValue) -> SqlValue {
+    match value {
+        AnyValue::Null => SqlValue::Null,
+        AnyValue::Boolean(value) => SqlValue::Integer(value as i64),
+        AnyValue::Utf8(value) => SqlValue::Text(value.to_owned()),
+        AnyValue::UInt8(value) => SqlValue::Integer(value as i64),
+        AnyValue::UInt16(value) => SqlValue::Integer(value as i64),
+        AnyValue::UInt32(value) => SqlValue::Integer(value as i64),
+        // sqlite integers are signed 64 bit
+        AnyValue::UInt64(value) => i64::try_from(value)
+            .map(SqlValue::Integer)
+            .unwrap_or_else(|_| SqlValue::Text(value.to_string())),
+        AnyValue::Int8(value) => SqlValue::Integer(value as i64),
+        AnyValue::Int16(value) => SqlValue::Integer(value as i64),
+        AnyValue::Int32(value) => SqlValue::Integer(value as i64),
+        AnyValue::Int64(value) => SqlValue::Integer(value),
+        AnyValue::Float32(value) => SqlValue::Real(value as f64),
+        AnyValue::Float64(value) => SqlValue::Real(value),
+        value => SqlValue::Text(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_metadata, SqliteWriter};
+    use crate::cli::config::Dataset;
+    use crate::export::database::DatabaseWriter;
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    #[test]
+    fn test_upsert() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("upsert.db");
+        let mut writer = SqliteWriter::open(path.to_str().unwrap()).unwrap();
+        let chunk = |numbers: &[u64]| {
+            let hashes: Vec<String> = numbers.iter().map(|n| format!("0x{:x}", n)).collect();
+            DataFrame::new(vec![
+                Series::new("number", numbers),
+                Series::new("hash", hashes),
+            ])
+            .unwrap()
+        };
+        writer
+            .write_chunk(&[(Dataset::Blocks, chunk(&[1, 2, 3]))])
+            .unwrap();
+        writer
+            .write_chunk(&[(Dataset::Blocks, chunk(&[3, 4]))])
+            .unwrap();
+
+        let count: i64 = writer
+            .connection
+            .query_row("SELECT count(*) FROM blocks", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 4);
+
+        // the metadata of later chunks widens the range of the table
+        let metadata = |range: &str| {
+            vec![
+                ("dataset".to_owned(), "blocks".to_owned()),
+                ("range".to_owned(), range.to_owned()),
+            ]
+        };
+        writer
+            .write_metadata(Dataset::Blocks, metadata("1:3"))
+            .unwrap();
+        writer
+            .write_metadata(Dataset::Blocks, metadata("3:4"))
+            .unwrap();
+        assert_eq!(read_metadata(&path).unwrap(), metadata("1:4"));
+    }
+}
@@ -0,0 +1,171 @@
+use serde_json::Value;
+use std::fmt;
+
+/// Unsigned 256-bit integer of quantity fields, little-endian u64 limbs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    /// Reads a json number, integers past u64 included, a 0x prefixed hex string or a
+    /// decimal string
+    pub fn from_value(value: &Value) -> Option<U256> {
+        match value {
+            Value::Number(number) => number
+                .as_u64()
+                .map(U256::from)
+                .or_else(|| number.as_f64().and_then(U256::from_f64)),
+            Value::String(text) => U256::parse(text),
+            _ => None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<U256> {
+        let (digits, radix) = match text.strip_prefix("0x") {
+            Some(digits) => (digits, 16),
+            None => (text, 10),
+        };
+        if digits.is_empty() {
+            return None;
+        }
+        digits.chars().try_fold(U256::default(), |number, digit| {
+            number.mul_add(radix as u64, digit.to_digit(radix)? as u64)
+        })
+    }
+
+    fn mul_add(mut self, mul: u64, add: u64) -> Option<U256> {
+        let mut carry = add as u128;
+        for limb in self.0.iter_mut() {
+            let product = *limb as u128 * mul as u128 + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        (carry == 0).then_some(self)
+    }
+
+    // divides in place and returns the remainder
+    fn div_rem(&mut self, div: u64) -> u64 {
+        let mut remainder = 0u128;
+        for limb in self.0.iter_mut().rev() {
+            let dividend = (remainder << 64) | *limb as u128;
+            *limb = (dividend / div as u128) as u64;
+            remainder = dividend % div as u128;
+        }
+        remainder as u64
+    }
+
+    /// Exact value of a non-negative float without a fraction
+    pub fn from_f64(value: f64) -> Option<U256> {
+        if !(value >= 0.0 && value.fract() == 0.0 && value < 2f64.powi(256)) {
+            return None;
+        }
+        if value < 2f64.powi(64) {
+            return Some(U256::from(value as u64));
+        }
+        // above 2^64 the float is its 53 bit mantissa shifted left
+        let bits = value.to_bits();
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let shift = ((bits >> 52) & 0x7ff) as i64 - 1075;
+        (0..shift).try_fold(U256::from(mantissa), |number, _| number.mul_add(2, 0))
+    }
+
+    pub fn high(&self) -> u128 {
+        ((self.0[3] as u128) << 64) | self.0[2] as u128
+    }
+
+    pub fn low(&self) -> u128 {
+        ((self.0[1] as u128) << 64) | self.0[0] as u128
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0.iter().rev().fold(0.0, |number, limb| {
+            number * 18446744073709551616.0 + *limb as f64
+        })
+    }
+
+    pub fn to_i128(&self) -> Option<i128> {
+        match self.high() {
+            0 => i128::try_from(self.low()).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(value: u64) -> Self {
+        U256([value, 0, 0, 0])
+    }
+}
+
+impl fmt::Display for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const CHUNK: u64 = 10_000_000_000_000_000_000;
+        let mut rest = *self;
+        let mut chunks = vec![rest.div_rem(CHUNK)];
+        while rest != U256::default() {
+            chunks.push(rest.div_rem(CHUNK));
+        }
+        write!(f, "{}", chunks.pop().unwrap_or_default())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:019}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::U256;
+    use serde_json::json;
+
+    #[test]
+    fn test_u256() {
+        let max = format!("0x{}", "f".repeat(64));
+        let max = U256::parse(&max).unwrap();
+        assert_eq!(
+            max.to_string(),
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+        );
+        assert_eq!(max.high(), u128::MAX);
+        assert_eq!(max.to_i128(), None);
+        assert!(U256::parse(&format!("0x1{}", "0".repeat(64))).is_none());
+
+        // total difficulty of mainnet is above u64
+        let difficulty = U256::from_value(&json!("58750003716598352816469")).unwrap();
+        assert_eq!(difficulty.to_string(), "58750003716598352816469");
+        assert_eq!(difficulty.to_i128(), Some(58750003716598352816469));
+        assert_eq!(
+            U256::from_value(&json!("0xde0b6b3a7640000"))
+                .unwrap()
+                .to_f64(),
+            1e18
+        );
+        assert_eq!(U256::from_value(&json!(0)).unwrap().to_string(), "0");
+        assert!(U256::from_value(&json!(null)).is_none());
+        assert!(U256::from_value(&json!("0x")).is_none());
+        assert!(U256::from_value(&json!("12a")).is_none());
+
+        assert_eq!(
+            U256::from_f64(1e18),
+            Some(U256::from(1_000_000_000_000_000_000))
+        );
+        assert_eq!(
+            U256::from_f64(2f64.powi(70)).unwrap().to_string(),
+            "1180591620717411303424"
+        );
+        assert_eq!(U256::from_f64(1.5), None);
+        assert_eq!(U256::from_f64(-1.0), None);
+        assert!(difficulty > U256::from(u64::MAX));
+    }
+}
@@ -0,0 +1,65 @@
+use crate::cli::config::HttpConfig;
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::{Client, Proxy, Response};
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Archive endpoint together with the client used to query it
+#[derive(Debug, Clone)]
+pub struct Archive {
+    pub url: String,
+    pub client: Client,
+    pub read_timeout: Duration,
+}
+
+impl Archive {
+    pub fn new(url: &str, http: &HttpConfig) -> Result<Self> {
+        Ok(Archive {
+            url: url.to_owned(),
+            client: build_client(http)?,
+            read_timeout: http.read_timeout,
+        })
+    }
+}
+
+/// Builds the client shared by every archive request of a run
+pub fn build_client(http: &HttpConfig) -> Result<Client> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &http.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("Invalid header name: {}", name))?;
+        let value = HeaderValue::from_str(value)
+            .with_context(|| format!("Invalid value for header {}", name))?;
+        headers.append(name, value);
+    }
+    if let Some(token) = &http.bearer_token {
+        let mut value =
+            HeaderValue::from_str(&format!("Bearer {}", token)).context("Invalid bearer token")?;
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+
+    let mut builder = Client::builder()
+        .connect_timeout(http.connect_timeout)
+        .default_headers(headers)
+        .user_agent(http.user_agent.as_str())
+        .gzip(http.compression)
+        .brotli(http.compression);
+    if let Some(proxy) = &http.proxy {
+        builder = builder.proxy(Proxy::all(proxy).context("Invalid proxy url")?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Reads a response body, failing when no data arrives within `read_timeout`
+pub async fn read_text(mut response: Response, read_timeout: Duration) -> Result<String> {
+    let mut body = Vec::new();
+    while let Some(chunk) = timeout(read_timeout, response.chunk())
+        .await
+        .context("Timed out reading archive response")??
+    {
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8(body)?)
+}
@@ -1,144 +1,694 @@
+use crate::cli::config::{Dataset, Range, Relation};
+use crate::fetcher::client::{read_text, Archive};
+use crate::fetcher::validate::BlockValidator;
+use anyhow::{anyhow, Context, Result};
 use crossbeam::channel::Sender;
-use reqwest::{self, Client};
-use serde::Deserialize;
 use serde_json::{json, to_string, Map, Value};
 use std::{
-    collections::HashMap,
-    io::{self, ErrorKind, Result as IoResult},
-    time::Instant,
+    collections::{btree_map::Entry, BTreeMap, HashMap, HashSet},
+    io::{Error, ErrorKind, Result as IoResult},
+    time::Duration,
 };
-use tokio;
+use tokio::time::{sleep, timeout};
 /// Chunk of blocks
-use utils::archive::{get_height, get_worker};
+use utils::archive::get_worker;
 use utils::utils::normalize_progess;
 
-use crate::{cli::config::Dataset, export::fields};
+const MAX_CHUNK_SIZE: usize = 10 * 1024 * 1024; // 10 MB in bytes
+const MAX_RETRIES: usize = 3;
+// wait before the first retry of a chunk, doubled for every further attempt
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+// filter values sent in one request, longer lists are split over several queries
+const MAX_FILTER_VALUES: usize = 1000;
+
+// header fields the validator and date partitioning need, requested on top of the user fields
+const HEADER_FIELDS: [&str; 4] = ["number", "hash", "parentHash", "timestamp"];
+
+fn field_map(fields: &[String], required: &[&str]) -> Map<String, Value> {
+    fields
+        .iter()
+        .map(String::as_str)
+        .chain(required.iter().copied())
+        .map(|field| (field.to_owned(), json!(true)))
+        .collect::<Map<_, _>>()
+}
+
+/// Archive field names behind the output columns of a dataset
+fn archive_fields(dataset: Dataset, fields: &[String]) -> Vec<String> {
+    let mut archive_fields: Vec<String> = Vec::new();
+    for field in fields {
+        let names: &[&str] = match (dataset, field.as_str()) {
+            // taken from the block header
+            (_, "blockNumber") => &[],
+            (Dataset::Logs, "topic0" | "topic1" | "topic2" | "topic3") => &["topics"],
+            (Dataset::Traces, "from") => &["callFrom", "createFrom"],
+            (Dataset::Traces, "to") => &["callTo", "createResultAddress"],
+            (Dataset::Traces, "value") => &["callValue", "createValue"],
+            (Dataset::Traces, "input") => &["callInput", "createInit"],
+            (Dataset::Traces, "gasUsed") => &["callResultGasUsed", "createResultGasUsed"],
+            (_, field) => &[field],
+        };
+        for name in names {
+            if !archive_fields.iter().any(|f| f == name) {
+                archive_fields.push(name.to_string());
+            }
+        }
+    }
+    archive_fields
+}
+
+/// What to request from the archive, shared by every chunk of a run
 #[derive(Debug, Clone)]
+pub struct Query {
+    pub dataset: Dataset,
+    pub fields: Vec<String>,
+    pub options: HashMap<String, Vec<String>>,
+    pub relations: Vec<Relation>,
+}
 
-struct Range(u64, u64);
-const MAX_CHUNK_SIZE: usize = 10 * 1024 * 1024; // 10 MB in bytes
+impl Query {
+    /// Filtered queries only get blocks with matches back, which is much faster
+    /// for sparse data. The archive still ends every response with the last block
+    /// it scanned, so progress keeps moving through empty stretches.
+    pub fn include_all_blocks(&self) -> bool {
+        self.dataset == Dataset::Blocks || self.options.is_empty()
+    }
+}
 
 fn create_query_json(
-    dataset: Dataset,
-    start_block: u64,
-    fields: &[String],
+    query: &Query,
     options: &HashMap<String, Vec<String>>,
+    start_block: u64,
+    end_block: u64,
 ) -> Value {
-    let field_map = fields
+    let fields = archive_fields(query.dataset, &query.fields);
+    let mut field_selection = Map::new();
+    for relation in &query.relations {
+        let key = match relation.dataset {
+            Dataset::Transactions => "transaction",
+            Dataset::Logs => "log",
+            Dataset::Traces => "trace",
+            Dataset::StateDiffs => "stateDiff",
+            Dataset::Blocks => continue,
+        };
+        let relation_fields = archive_fields(relation.dataset, &relation.fields);
+        field_selection.insert(key.to_owned(), json!(field_map(&relation_fields, &[])));
+    }
+    let mut request = options
         .iter()
-        .map(|field| (field.clone(), json!(true)))
+        .map(|(key, values)| (key.clone(), json!(values)))
         .collect::<Map<_, _>>();
 
-    match dataset {
-        Dataset::Blocks => json!({
-            "fields": {"block": field_map},
-            "fromBlock": start_block,
-            "includeAllBlocks": true,
-        }),
+    match query.dataset {
+        Dataset::Blocks => {
+            field_selection.insert(
+                "block".to_owned(),
+                json!(field_map(&fields, &HEADER_FIELDS)),
+            );
+            let mut request = json!({
+                "fields": field_selection,
+                "fromBlock": start_block,
+                "toBlock": end_block,
+                "includeAllBlocks": true,
+            });
+            // an empty request matches every transaction of the block
+            if query
+                .relations
+                .iter()
+                .any(|relation| relation.dataset == Dataset::Transactions)
+            {
+                request["transactions"] = json!([{}]);
+            }
+            request
+        }
 
         Dataset::Transactions => {
-            let options_json = json!(options);
+            for relation in &query.relations {
+                match relation.dataset {
+                    Dataset::Logs => request.insert("logs".to_owned(), json!(true)),
+                    Dataset::Traces => request.insert("traces".to_owned(), json!(true)),
+                    Dataset::StateDiffs => request.insert("stateDiffs".to_owned(), json!(true)),
+                    _ => None,
+                };
+            }
+            field_selection.insert("block".to_owned(), json!(field_map(&[], &HEADER_FIELDS)));
+            field_selection.insert(
+                "transaction".to_owned(),
+                json!(field_map(&fields, &["transactionIndex"])),
+            );
             json!({
-                "transactions": [options_json],
-                "fields": {
-                    "block": {},
-                    "transaction": field_map
-                },
+                "transactions": [request],
+                "fields": field_selection,
                 "fromBlock": start_block,
-                "includeAllBlocks": true,
+                "toBlock": end_block,
+                "includeAllBlocks": query.include_all_blocks(),
             })
         }
 
         Dataset::Logs => {
-            todo!("Logs dataset handling not implemented")
+            for relation in &query.relations {
+                match relation.dataset {
+                    Dataset::Transactions => request.insert("transaction".to_owned(), json!(true)),
+                    Dataset::Traces => request.insert("transactionTraces".to_owned(), json!(true)),
+                    _ => None,
+                };
+            }
+            field_selection.insert("block".to_owned(), json!(field_map(&[], &HEADER_FIELDS)));
+            field_selection.insert(
+                "log".to_owned(),
+                json!(field_map(&fields, &["logIndex", "transactionIndex"])),
+            );
+            json!({
+                "logs": [request],
+                "fields": field_selection,
+                "fromBlock": start_block,
+                "toBlock": end_block,
+                "includeAllBlocks": query.include_all_blocks(),
+            })
         }
+
+        Dataset::Traces | Dataset::StateDiffs => {
+            unreachable!("{} are only fetched as related data", query.dataset.name())
+        }
+    }
+}
+
+/// Splits filter lists longer than `max_values` into several option sets that
+/// together match the same items
+fn split_options(
+    options: &HashMap<String, Vec<String>>,
+    max_values: usize,
+) -> Vec<HashMap<String, Vec<String>>> {
+    let mut option_sets = vec![HashMap::new()];
+    for (key, values) in options {
+        let chunks: Vec<&[String]> = if values.is_empty() {
+            vec![values]
+        } else {
+            values.chunks(max_values).collect()
+        };
+        option_sets = option_sets
+            .into_iter()
+            .flat_map(|option_set| {
+                chunks.iter().map(move |chunk| {
+                    let mut option_set = option_set.clone();
+                    option_set.insert(key.clone(), chunk.to_vec());
+                    option_set
+                })
+            })
+            .collect();
+    }
+    option_sets
+}
+
+// fields telling the items of a block apart
+fn item_key_fields(list: &str) -> &'static [&'static str] {
+    match list {
+        "transactions" => &["transactionIndex"],
+        "logs" => &["logIndex"],
+        "traces" => &["transactionIndex", "traceAddress"],
+        _ => &["transactionIndex", "address", "key"],
+    }
+}
+
+// items without their key fields are told apart by their whole value
+fn item_key(list: &str, item: &Value) -> String {
+    let fields = item_key_fields(list);
+    if fields.iter().all(|field| item[*field].is_null()) {
+        return item.to_string();
+    }
+    fields
+        .iter()
+        .map(|field| item[*field].to_string())
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+// position of an item in its block, traces follow their call tree within a transaction
+fn item_order(list: &str, item: &Value) -> (Option<u64>, Vec<u64>) {
+    let path = |value: &Value| -> Vec<u64> {
+        value
+            .as_array()
+            .map(|path| path.iter().filter_map(|index| index.as_u64()).collect())
+            .unwrap_or_default()
+    };
+    match list {
+        "logs" => (item["logIndex"].as_u64(), Vec::new()),
+        "traces" => (
+            item["transactionIndex"].as_u64(),
+            path(&item["traceAddress"]),
+        ),
+        _ => (item["transactionIndex"].as_u64(), Vec::new()),
+    }
+}
+
+// the same item can come back from several split queries
+fn merge_items(into: &mut Value, from: &Value, list: &str) {
+    let items = match from.get(list).and_then(|items| items.as_array()) {
+        Some(items) => items,
+        None => return,
+    };
+    let target = match into.as_object_mut() {
+        Some(block) => block.entry(list).or_insert_with(|| json!([])),
+        None => return,
+    };
+    if let Some(target) = target.as_array_mut() {
+        let mut keys: HashSet<String> = target.iter().map(|item| item_key(list, item)).collect();
+        for item in items {
+            if keys.insert(item_key(list, item)) {
+                target.push(item.clone());
+            }
+        }
+        target.sort_by_cached_key(|item| item_order(list, item));
     }
 }
 
+fn merge_blocks(merged: &mut BTreeMap<u64, Value>, blocks: Vec<Value>) -> Result<()> {
+    for block in blocks {
+        let number = block["header"]["number"]
+            .as_u64()
+            .ok_or_else(|| anyhow!("block without number in worker response"))?;
+        match merged.entry(number) {
+            Entry::Vacant(entry) => {
+                entry.insert(block);
+            }
+            Entry::Occupied(mut entry) => {
+                // split queries must see the same chain
+                let hash = &entry.get()["header"]["hash"];
+                if *hash != block["header"]["hash"] {
+                    return Err(anyhow!(
+                        "block {} has hash {} in one split query and {} in another",
+                        number,
+                        hash,
+                        block["header"]["hash"]
+                    ));
+                }
+                for list in ["transactions", "logs", "traces", "stateDiffs"] {
+                    merge_items(entry.get_mut(), &block, list);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 pub async fn fetch_block_chunk(
-    dataset: Dataset,
-    start_block: u64,
-    fields: &Vec<String>,
+    query: &Query,
     options: &HashMap<String, Vec<String>>,
-    client: Client,
-) -> Result<(Vec<Value>, u64), reqwest::Error> {
-    let block_query = create_query_json(dataset, start_block, fields, &options);
+    start_block: u64,
+    end_block: u64,
+    archive: &Archive,
+) -> Result<Vec<Value>> {
+    let block_query = create_query_json(query, options, start_block, end_block);
     //println!("BLOCK QUERY: {:?}", block_query);
-    let worker = get_worker(
-        "https://v2.archive.subsquid.io/network/ethereum-mainnet",
-        &start_block.to_string(),
+    let worker = timeout(
+        archive.read_timeout,
+        get_worker(&archive.client, &archive.url, &start_block.to_string()),
     )
-    .await?;
+    .await
+    .context("Timed out waiting for a worker")??;
     //println!("WORKER: {:?}", worker);
-    let result = client
-        .post(worker)
-        .json::<serde_json::Value>(&block_query)
-        .send()
-        .await?
-        .text()
-        .await?;
-
-    let blocks_value: Value = serde_json::from_str::<Value>(&result).unwrap();
-    let blocks = blocks_value.as_array().unwrap();
-    let next_block = blocks
-        .last()
-        .and_then(|b| b["header"]["number"].as_u64())
-        .unwrap()
-        + 1;
-    // println!("NEXT BLOCK: {:?}", next_block);
-    Ok((blocks.to_vec(), next_block))
+    let response = timeout(
+        archive.read_timeout,
+        archive
+            .client
+            .post(worker)
+            .json::<serde_json::Value>(&block_query)
+            .send(),
+    )
+    .await
+    .context("Timed out waiting for worker response")??
+    .error_for_status()?;
+    let result = read_text(response, archive.read_timeout).await?;
+
+    match serde_json::from_str::<Value>(&result)? {
+        Value::Array(blocks) => Ok(blocks),
+        _ => Err(anyhow!("unexpected worker response: {}", result)),
+    }
 }
 
-pub async fn fetch_loop(
-    dataset: Dataset,
-    mut start_block: u64,
+/// Fetches the chunk starting at `start_block`. When the filters are too long for
+/// one request, the first query decides how far the chunk goes and the remaining
+/// queries are fetched over the same blocks, validated and merged in. The merged
+/// chunk is validated by the caller.
+async fn fetch_split_chunk(
+    query: &Query,
+    start_block: u64,
     end_block: u64,
-    fields: Vec<String>,
-    options: HashMap<String, Vec<String>>,
-    write_tx: Sender<Vec<Value>>,
-    stats_tx: Sender<u64>,
-) -> IoResult<()> {
-    loop {
-        let client: Client = reqwest::Client::new();
-        let (block_chunk, next_block) =
-            fetch_block_chunk(dataset, start_block, &fields, &options, client)
-                .await
-                .unwrap();
-        let mut data_chunk = Vec::new();
-        let mut current_size = 0;
-
-        for data in block_chunk {
-            let serialized: String = to_string(&data).unwrap();
-            if current_size + serialized.len() > MAX_CHUNK_SIZE {
-                write_tx.send(data_chunk).unwrap();
-                data_chunk = Vec::new();
-                current_size = 0;
+    archive: &Archive,
+) -> Result<Vec<Value>> {
+    let option_sets = split_options(&query.options, MAX_FILTER_VALUES);
+    let blocks = fetch_block_chunk(query, &option_sets[0], start_block, end_block, archive).await?;
+    let chunk_end = match blocks.last().and_then(|b| b["header"]["number"].as_u64()) {
+        Some(chunk_end) if option_sets.len() > 1 => chunk_end,
+        _ => return Ok(blocks),
+    };
+
+    let mut merged = BTreeMap::new();
+    merge_blocks(&mut merged, blocks)?;
+    for options in &option_sets[1..] {
+        // the responses of one query continue each other
+        let mut validator = BlockValidator::new(query.include_all_blocks(), chunk_end);
+        let mut from_block = start_block;
+        while from_block <= chunk_end {
+            let blocks = fetch_block_chunk(query, options, from_block, chunk_end, archive).await?;
+            validator.validate(from_block, &blocks)?;
+            match blocks.last().and_then(|b| b["header"]["number"].as_u64()) {
+                Some(last_block) => from_block = last_block + 1,
+                None => break,
             }
-            current_size += serialized.len();
-            data_chunk.push(data);
+            merge_blocks(&mut merged, blocks)?;
+        }
+    }
+    Ok(merged.into_values().collect())
+}
+
+/// Fetches and validates the chunk starting at `start_block`, refetching it when
+/// the response fails a check. Returns the blocks and the next block to fetch.
+async fn fetch_valid_chunk(
+    query: &Query,
+    start_block: u64,
+    end_block: u64,
+    archive: &Archive,
+    validator: &mut BlockValidator,
+) -> IoResult<(Vec<Value>, u64)> {
+    let mut attempt = 1;
+    loop {
+        let error = match fetch_split_chunk(query, start_block, end_block, archive).await {
+            Ok(blocks) => match validator.validate(start_block, &blocks) {
+                Ok(()) => {
+                    // the validator rejects empty responses, a last block always exists
+                    let last_block = blocks
+                        .last()
+                        .and_then(|b| b["header"]["number"].as_u64())
+                        .ok_or_else(|| {
+                            Error::new(
+                                ErrorKind::InvalidData,
+                                format!("chunk at block {} has no last block", start_block),
+                            )
+                        })?;
+                    return Ok((blocks, last_block + 1));
+                }
+                Err(e) => Error::new(ErrorKind::InvalidData, e),
+            },
+            Err(e) => Error::other(e),
+        };
+        if attempt >= MAX_RETRIES {
+            return Err(Error::new(
+                error.kind(),
+                format!(
+                    "chunk at block {} failed after {} attempts: {}",
+                    start_block, attempt, error
+                ),
+            ));
+        }
+        eprintln!(
+            "chunk at block {} failed (attempt {}/{}): {}, retrying",
+            start_block, attempt, MAX_RETRIES, error
+        );
+        // gives a rotating or lagging worker time before it is asked again
+        sleep(RETRY_DELAY * 2u32.pow(attempt as u32 - 1)).await;
+        attempt += 1;
+    }
+}
+
+/// Blocks handed to the write loop, covering the blocks `start` to `end`
+#[derive(Debug, PartialEq)]
+pub struct BlockChunk {
+    pub start: u64,
+    pub end: u64,
+    pub blocks: Vec<Value>,
+    // header of the block after the chunk when already fetched, files the spans
+    // without blocks, which have no timestamp of their own
+    pub following: Option<Value>,
+}
+
+fn block_number(block: &Value) -> u64 {
+    block["header"]["number"].as_u64().unwrap_or_default()
+}
+
+// last block of the `chunk_size` aligned span holding `block`
+fn aligned_end(block: u64, chunk_size: u64) -> u64 {
+    (block / chunk_size + 1) * chunk_size - 1
+}
+
+// without a chunk size, responses are cut into pieces of at most MAX_CHUNK_SIZE bytes,
+// together covering the blocks `start` to `end` the archive scanned
+fn split_by_size(blocks: Vec<Value>, start: u64, end: u64) -> Vec<BlockChunk> {
+    let mut chunks = Vec::new();
+    let mut data_chunk: Vec<Value> = Vec::new();
+    let mut current_size = 0;
+    for data in blocks {
+        let serialized: String = to_string(&data).unwrap();
+        if current_size + serialized.len() > MAX_CHUNK_SIZE && !data_chunk.is_empty() {
+            chunks.push(std::mem::take(&mut data_chunk));
+            current_size = 0;
         }
-        // Send any remaining data
-        if !data_chunk.is_empty() {
-            write_tx.send(data_chunk).unwrap();
+        current_size += serialized.len();
+        data_chunk.push(data);
+    }
+    if !data_chunk.is_empty() {
+        chunks.push(data_chunk);
+    }
+    // every piece ends right before the first block of the next
+    let starts: Vec<u64> = chunks
+        .iter()
+        .skip(1)
+        .map(|blocks| blocks.first().map(block_number).unwrap_or_default())
+        .collect();
+    let mut chunk_start = start;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, blocks)| {
+            let chunk_end = starts.get(i).map_or(end, |next| next - 1);
+            let chunk = BlockChunk {
+                start: chunk_start,
+                end: chunk_end,
+                blocks,
+                following: None,
+            };
+            chunk_start = chunk_end + 1;
+            chunk
+        })
+        .collect()
+}
+
+/// Moves the blocks of every span the archive has scanned past out of `pending`,
+/// spans without matching blocks included
+fn split_aligned(
+    pending: &mut Vec<Value>,
+    span_start: &mut u64,
+    next_block: u64,
+    end_block: u64,
+    chunk_size: u64,
+) -> Vec<BlockChunk> {
+    let mut chunks = Vec::new();
+    while *span_start <= end_block {
+        let span_end = aligned_end(*span_start, chunk_size).min(end_block);
+        if next_block <= span_end {
+            break;
         }
-        let normalized_progress = normalize_progess(start_block, end_block, next_block);
-        let _ = stats_tx.send(normalized_progress);
-        //break or continues
-        match next_block {
-            _ if next_block > end_block => {
-                break;
+        let split = pending.partition_point(|block| block_number(block) <= span_end);
+        let rest = pending.split_off(split);
+        let following = rest.first().map(|block| block["header"].clone());
+        chunks.push(BlockChunk {
+            start: *span_start,
+            end: span_end,
+            blocks: std::mem::replace(pending, rest),
+            following,
+        });
+        *span_start = span_end + 1;
+    }
+    chunks
+}
+
+/// Fetches `ranges` one after another, sending chunks to the write loop and the share of
+/// blocks done to the stats loop
+pub async fn fetch_loop(
+    query: Query,
+    ranges: Vec<Range>,
+    chunk_size: Option<u64>,
+    archive: Archive,
+    write_tx: Sender<BlockChunk>,
+    stats_tx: Sender<u64>,
+) -> IoResult<()> {
+    let total_blocks: u64 = ranges.iter().map(|range| range.end - range.start + 1).sum();
+    let mut done_blocks = 0;
+    for range in ranges {
+        let (mut start_block, end_block) = (range.start, range.end);
+        // ranges are apart, every one starts a new chain of blocks
+        let mut validator = BlockValidator::new(query.include_all_blocks(), end_block);
+        // blocks buffered across responses until their aligned span is complete
+        let mut pending = Vec::new();
+        let mut span_start = start_block;
+        loop {
+            let (block_chunk, next_block) =
+                fetch_valid_chunk(&query, start_block, end_block, &archive, &mut validator).await?;
+            let chunks = match chunk_size {
+                Some(chunk_size) => {
+                    pending.extend(block_chunk);
+                    split_aligned(
+                        &mut pending,
+                        &mut span_start,
+                        next_block,
+                        end_block,
+                        chunk_size,
+                    )
+                }
+                None => split_by_size(block_chunk, start_block, next_block.min(end_block + 1) - 1),
+            };
+            for chunk in chunks {
+                write_tx
+                    .send(chunk)
+                    .map_err(|_| Error::new(ErrorKind::BrokenPipe, "write loop stopped"))?;
             }
-            _ => {
-                start_block = next_block;
+            let current_block = done_blocks + next_block.min(end_block + 1) - range.start;
+            let normalized_progress = normalize_progess(0, total_blocks, current_block);
+            let _ = stats_tx.send(normalized_progress);
+            //break or continues
+            match next_block {
+                _ if next_block > end_block => {
+                    break;
+                }
+                _ => {
+                    start_block = next_block;
+                }
             }
         }
+        done_blocks += end_block - range.start + 1;
     }
-    let _ = write_tx.send(Vec::new());
+    // dropping write_tx closes the channel and ends the write loop
     let _ = stats_tx.send(0);
 
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{
+        merge_blocks, split_aligned, split_by_size, split_options, BlockChunk, MAX_CHUNK_SIZE,
+    };
+    use anyhow::Result;
+    use serde_json::json;
+    use std::collections::{BTreeMap, HashMap};
+
+    #[test]
+    fn test_split_options() {
+        let mut options = HashMap::new();
+        options.insert(
+            "to".to_owned(),
+            (0..5).map(|i| i.to_string()).collect::<Vec<_>>(),
+        );
+        options.insert("sighash".to_owned(), vec!["0xa9059cbb".to_owned()]);
+        let option_sets = split_options(&options, 2);
+        assert_eq!(option_sets.len(), 3);
+        assert_eq!(option_sets[2]["to"], vec!["4".to_owned()]);
+        assert!(option_sets
+            .iter()
+            .all(|option_set| option_set["sighash"] == options["sighash"]));
+    }
+
+    #[test]
+    fn test_split_aligned() {
+        let blocks = |numbers: &[u64]| -> Vec<serde_json::Value> {
+            numbers
+                .iter()
+                .map(|number| json!({"header": {"number": number}}))
+                .collect()
+        };
+        let mut pending = blocks(&[150, 180, 230]);
+        let mut span_start = 150;
+        // the response ends inside the span 200..=299
+        let chunks = split_aligned(&mut pending, &mut span_start, 250, 1000, 100);
+        assert_eq!(
+            chunks,
+            vec![BlockChunk {
+                start: 150,
+                end: 199,
+                blocks: blocks(&[150, 180]),
+                following: Some(json!({"number": 230})),
+            }]
+        );
+        assert_eq!((span_start, pending.len()), (200, 1));
+
+        // a sparse response can complete several spans, the last one is cut at the end block
+        pending.extend(blocks(&[450]));
+        let chunks = split_aligned(&mut pending, &mut span_start, 451, 450, 100);
+        let spans: Vec<_> = chunks
+            .iter()
+            .map(|c| (c.start, c.end, c.blocks.len()))
+            .collect();
+        assert_eq!(spans, vec![(200, 299, 1), (300, 399, 0), (400, 450, 1)]);
+        assert_eq!(chunks[1].following, Some(json!({"number": 450})));
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_split_by_size() {
+        // a sparse response covers the blocks it scanned, not only those it returned
+        let chunks = split_by_size(vec![json!({"header": {"number": 180}})], 100, 199);
+        let spans: Vec<_> = chunks
+            .iter()
+            .map(|c| (c.start, c.end, c.blocks.len()))
+            .collect();
+        assert_eq!(spans, vec![(100, 199, 1)]);
+
+        let big = "0".repeat(MAX_CHUNK_SIZE);
+        let blocks = [120u64, 150, 180]
+            .iter()
+            .map(|number| json!({"header": {"number": number}, "extra": big}))
+            .collect();
+        let spans: Vec<_> = split_by_size(blocks, 100, 199)
+            .iter()
+            .map(|c| (c.start, c.end))
+            .collect();
+        assert_eq!(spans, vec![(100, 149), (150, 179), (180, 199)]);
+    }
+
+    #[test]
+    fn test_merge_blocks() -> Result<()> {
+        let mut merged = BTreeMap::new();
+        merge_blocks(
+            &mut merged,
+            vec![json!({"header": {"number": 1}, "transactions": [{"transactionIndex": 2}]})],
+        )?;
+        merge_blocks(
+            &mut merged,
+            vec![
+                json!({"header": {"number": 1}, "transactions": [{"transactionIndex": 0}, {"transactionIndex": 2}]}),
+                json!({"header": {"number": 2}, "transactions": []}),
+            ],
+        )?;
+        assert_eq!(merged.len(), 2);
+        assert_eq!(
+            merged[&1]["transactions"],
+            json!([{"transactionIndex": 0}, {"transactionIndex": 2}])
+        );
+
+        // traces of a transaction are ordered by their call tree
+        let traces = |addresses: serde_json::Value| {
+            let traces: Vec<_> = addresses
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|address| json!({"transactionIndex": 0, "traceAddress": address}))
+                .collect();
+            json!({"header": {"number": 3}, "traces": traces})
+        };
+        merge_blocks(&mut merged, vec![traces(json!([[], [1]]))])?;
+        merge_blocks(&mut merged, vec![traces(json!([[0], [0, 0], [1]]))])?;
+        assert_eq!(
+            merged[&3]["traces"],
+            traces(json!([[], [0], [0, 0], [1]]))["traces"]
+        );
+
+        // a block seen with another hash belongs to another chain
+        let reorged = json!({"header": {"number": 2, "hash": "0xbb"}, "transactions": []});
+        assert!(merge_blocks(&mut merged, vec![reorged]).is_err());
+        Ok(())
+    }
+}
+
 // #[tokio::test]
 
 // async fn test_fetch_block_chunk() {
@@ -1,5 +1,8 @@
 //pub mod block_fetcher;
+#[allow(clippy::module_inception)]
 pub mod fetcher;
 //pub mod traits;
 // pub mod transaction_fetcher;
 // pub mod transactions;
+pub mod client;
+pub mod validate;
@@ -0,0 +1,277 @@
+use serde_json::Value;
+use std::fmt;
+
+/// Integrity check that a worker response failed
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+    Empty {
+        start: u64,
+    },
+    MissingHeader {
+        position: usize,
+    },
+    OutOfRange {
+        number: u64,
+        start: u64,
+        end: u64,
+    },
+    NotIncreasing {
+        previous: u64,
+        number: u64,
+    },
+    Gap {
+        expected: u64,
+        number: u64,
+    },
+    ParentHash {
+        number: u64,
+        expected: String,
+        found: String,
+    },
+    TransactionOrder {
+        number: u64,
+        previous: u64,
+        index: u64,
+    },
+    LogOrder {
+        number: u64,
+        previous: u64,
+        index: u64,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty { start } => {
+                write!(f, "empty response: no blocks returned from block {}", start)
+            }
+            Self::MissingHeader { position } => write!(
+                f,
+                "missing header: block at position {} has no number or hash",
+                position
+            ),
+            Self::OutOfRange { number, start, end } => write!(
+                f,
+                "block range: block {} is outside of requested range {}:{}",
+                number, start, end
+            ),
+            Self::NotIncreasing { previous, number } => write!(
+                f,
+                "block order: block {} follows block {}",
+                number, previous
+            ),
+            Self::Gap { expected, number } => write!(
+                f,
+                "block continuity: expected block {}, got block {}",
+                expected, number
+            ),
+            Self::ParentHash {
+                number,
+                expected,
+                found,
+            } => write!(
+                f,
+                "parent hash: block {} has parentHash {}, previous block hash is {}",
+                number, found, expected
+            ),
+            Self::TransactionOrder {
+                number,
+                previous,
+                index,
+            } => write!(
+                f,
+                "transaction order: in block {} transaction {} follows transaction {}",
+                number, index, previous
+            ),
+            Self::LogOrder {
+                number,
+                previous,
+                index,
+            } => write!(
+                f,
+                "log order: in block {} log {} follows log {}",
+                number, index, previous
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Checks consecutive worker responses for one query.
+/// State only advances when a whole chunk passes, so a failed chunk can be refetched.
+#[derive(Debug)]
+pub struct BlockValidator {
+    include_all_blocks: bool,
+    end_block: u64,
+    last_block: Option<(u64, String)>,
+}
+
+impl BlockValidator {
+    pub fn new(include_all_blocks: bool, end_block: u64) -> Self {
+        BlockValidator {
+            include_all_blocks,
+            end_block,
+            last_block: None,
+        }
+    }
+
+    pub fn validate(&mut self, start_block: u64, blocks: &[Value]) -> Result<(), ValidationError> {
+        if blocks.is_empty() {
+            return Err(ValidationError::Empty { start: start_block });
+        }
+        let mut last_block = self.last_block.clone();
+
+        for (position, block) in blocks.iter().enumerate() {
+            let header = &block["header"];
+            let (number, hash) = match (header["number"].as_u64(), header["hash"].as_str()) {
+                (Some(number), Some(hash)) => (number, hash),
+                _ => return Err(ValidationError::MissingHeader { position }),
+            };
+            if number < start_block || number > self.end_block {
+                return Err(ValidationError::OutOfRange {
+                    number,
+                    start: start_block,
+                    end: self.end_block,
+                });
+            }
+
+            let expected = match &last_block {
+                Some((previous, _)) if *previous >= start_block => {
+                    if number <= *previous {
+                        return Err(ValidationError::NotIncreasing {
+                            previous: *previous,
+                            number,
+                        });
+                    }
+                    previous + 1
+                }
+                _ => start_block,
+            };
+            if self.include_all_blocks && number != expected {
+                return Err(ValidationError::Gap { expected, number });
+            }
+
+            if let Some((previous, previous_hash)) = &last_block {
+                if previous + 1 == number {
+                    if let Some(parent_hash) = header["parentHash"].as_str() {
+                        if parent_hash != previous_hash {
+                            return Err(ValidationError::ParentHash {
+                                number,
+                                expected: previous_hash.clone(),
+                                found: parent_hash.to_string(),
+                            });
+                        }
+                    }
+                }
+            }
+
+            check_index_order(block, "transactions", "transactionIndex").map_err(
+                |(previous, index)| ValidationError::TransactionOrder {
+                    number,
+                    previous,
+                    index,
+                },
+            )?;
+            check_index_order(block, "logs", "logIndex").map_err(|(previous, index)| {
+                ValidationError::LogOrder {
+                    number,
+                    previous,
+                    index,
+                }
+            })?;
+
+            last_block = Some((number, hash.to_string()));
+        }
+
+        self.last_block = last_block;
+        Ok(())
+    }
+}
+
+fn check_index_order(block: &Value, list: &str, index_field: &str) -> Result<(), (u64, u64)> {
+    let items = match block.get(list).and_then(|items| items.as_array()) {
+        Some(items) => items,
+        None => return Ok(()),
+    };
+    let mut previous: Option<u64> = None;
+    for item in items {
+        if let Some(index) = item[index_field].as_u64() {
+            if let Some(previous) = previous {
+                if index <= previous {
+                    return Err((previous, index));
+                }
+            }
+            previous = Some(index);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockValidator, ValidationError};
+    use serde_json::{json, Value};
+
+    fn block(number: u64, hash: &str, parent_hash: &str) -> Value {
+        json!({"header": {"number": number, "hash": hash, "parentHash": parent_hash}})
+    }
+
+    #[test]
+    fn test_contiguous_chunks() {
+        let mut validator = BlockValidator::new(true, 10);
+        let first = vec![block(1, "0x01", "0x00"), block(2, "0x02", "0x01")];
+        let second = vec![block(3, "0x03", "0x02")];
+        assert_eq!(validator.validate(1, &first), Ok(()));
+        assert_eq!(validator.validate(3, &second), Ok(()));
+    }
+
+    #[test]
+    fn test_gap() {
+        let mut validator = BlockValidator::new(true, 10);
+        let blocks = vec![block(1, "0x01", "0x00"), block(3, "0x03", "0x02")];
+        assert_eq!(
+            validator.validate(1, &blocks),
+            Err(ValidationError::Gap {
+                expected: 2,
+                number: 3
+            })
+        );
+        let mut sparse = BlockValidator::new(false, 10);
+        assert_eq!(sparse.validate(1, &blocks), Ok(()));
+    }
+
+    #[test]
+    fn test_parent_hash_across_chunks() {
+        let mut validator = BlockValidator::new(true, 10);
+        assert_eq!(validator.validate(1, &[block(1, "0x01", "0x00")]), Ok(()));
+        assert_eq!(
+            validator.validate(2, &[block(2, "0x02", "0xff")]),
+            Err(ValidationError::ParentHash {
+                number: 2,
+                expected: "0x01".to_owned(),
+                found: "0xff".to_owned()
+            })
+        );
+        // failed chunk does not advance the validator
+        assert_eq!(validator.validate(2, &[block(2, "0x02", "0x01")]), Ok(()));
+    }
+
+    #[test]
+    fn test_transaction_order() {
+        let mut validator = BlockValidator::new(true, 10);
+        let blocks = vec![json!({
+            "header": {"number": 1, "hash": "0x01", "parentHash": "0x00"},
+            "transactions": [{"transactionIndex": 0}, {"transactionIndex": 2}, {"transactionIndex": 1}]
+        })];
+        assert_eq!(
+            validator.validate(1, &blocks),
+            Err(ValidationError::TransactionOrder {
+                number: 1,
+                previous: 2,
+                index: 1
+            })
+        );
+    }
+}
@@ -1,38 +1,94 @@
-//use anyhow::Result as AnyhowResult;
+use anyhow::{anyhow, Result};
 use clap::Parser;
 use crossbeam::channel::unbounded;
-use little_squid_cli::cli::config::Config;
-use little_squid_cli::cli::opts::Opts;
-use little_squid_cli::fetcher::fetcher;
+use little_squid_cli::checkpoint::Checkpoint;
+use little_squid_cli::cli::config::{Config, Dataset};
+use little_squid_cli::cli::opts::{Command, Opts};
+use little_squid_cli::export::existing::{ranges_to_fetch, remove_temp_files};
+use little_squid_cli::export::metadata::read_metadata;
+use little_squid_cli::fetcher::client::Archive;
+use little_squid_cli::fetcher::fetcher::{self, Query};
 use little_squid_cli::progress::stats;
 use little_squid_cli::save;
-use std::io::Result;
+use std::path::Path;
 use std::thread;
-use tokio;
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config: Config = Opts::parse().try_into().unwrap();
-    println!("CONFIG: {:?}", config);
+    let opts = Opts::parse();
+    if let Some(Command::Inspect { files }) = &opts.command {
+        return inspect(files);
+    }
+    let config: Config = opts.try_into()?;
+    let archive = Archive::new(&config.archive, &config.http)?;
     let fields = config.fields.clone();
+    // nested blocks embed their transactions instead of writing them aside
+    let relations = if config.output.nested {
+        Vec::new()
+    } else {
+        config.relations.clone()
+    };
+    let filter = config.filter.clone();
+    let output = config.output.clone();
+    let datasets: Vec<Dataset> = std::iter::once(config.dataset)
+        .chain(relations.iter().map(|relation| relation.dataset))
+        .collect();
+    // files of a killed run never got their final name
+    for path in remove_temp_files(&datasets, &output)? {
+        eprintln!("Removed unfinished {}", path.display());
+    }
+    let mut ranges = ranges_to_fetch(config.range, &datasets, &output)?;
+    let checkpoint = if config.resume {
+        match Checkpoint::load(&config)? {
+            Some(checkpoint) => {
+                ranges = checkpoint.remaining(ranges);
+                Some(checkpoint)
+            }
+            None => {
+                eprintln!("No checkpoint to resume from, starting at the first block");
+                Checkpoint::new(&config)
+            }
+        }
+    } else {
+        Checkpoint::new(&config)
+    };
+    if ranges.is_empty() {
+        eprintln!("All blocks of the range are already written");
+        return Ok(());
+    }
     let start_time = std::time::Instant::now();
     //let (stat_tx, stat_rx) = unbounded();
 
     let (write_tx, write_rx) = unbounded();
 
     let (stat_tx, stat_rx) = unbounded();
+    let query = Query {
+        dataset: config.dataset,
+        fields: config.fields,
+        options: config.options,
+        relations: config.relations,
+    };
     let read_handle = tokio::spawn(fetcher::fetch_loop(
-        config.dataset,
-        config.range.start,
-        config.range.end,
-        config.fields,
-        config.options,
+        query,
+        ranges,
+        config.chunk_size,
+        archive,
         write_tx,
         stat_tx,
     ));
     let stats_handle = thread::spawn(move || stats::stats_loop(stat_rx));
 
     //let stats_handle = thread::spawn(move || stats::stats_loop(silent, stat_rx));
-    let write_handle = thread::spawn(move || save::write_loop(config.dataset, fields, write_rx));
+    let write_handle = thread::spawn(move || {
+        save::write_loop(
+            config.dataset,
+            fields,
+            relations,
+            filter,
+            output,
+            checkpoint,
+            write_rx,
+        )
+    });
 
     let read_io_result = read_handle.await?;
     let stats_io_result = stats_handle.join().unwrap();
@@ -43,6 +99,17 @@ async fn main() -> Result<()> {
     write_io_result?;
     let elapsed_time = start_time.elapsed();
 
-    println!("Elapsed time: {:?}", elapsed_time);
+    eprintln!("Elapsed time: {:?}", elapsed_time);
+    Ok(())
+}
+
+fn inspect(files: &[String]) -> Result<()> {
+    for file in files {
+        let metadata = read_metadata(Path::new(file)).map_err(|e| anyhow!("{}: {}", file, e))?;
+        println!("{}", file);
+        for (key, value) in metadata {
+            println!("  {}: {}", key, value);
+        }
+    }
     Ok(())
 }
@@ -14,11 +14,9 @@ pub fn stats_loop(stats_rx: Receiver<u64>) -> Result<()> {
         .unwrap();
     progress_bar.set_style(progress_bar_style);
 
-    loop {
-        let normalized_progress = stats_rx.recv().unwrap();
-
+    while let Ok(normalized_progress) = stats_rx.recv() {
         //let (current_progress, completed) = *progress.lock().unwrap();
-        progress_bar.set_position(normalized_progress as u64);
+        progress_bar.set_position(normalized_progress);
         if normalized_progress >= 100 {
             break;
         }
@@ -1,25 +1,102 @@
-use crate::cli::config::Dataset;
-use crate::export::export::save_to_file;
+use crate::checkpoint::Checkpoint;
+use crate::cli::config::{Dataset, Destination, OutputConfig, Relation};
+use crate::export::database::open_database;
+use crate::export::export::{chunk_to_dataframe, save_to_file, StdoutWriter};
+use crate::export::filter::RowFilter;
+use crate::export::metadata::file_metadata;
+use crate::fetcher::fetcher::BlockChunk;
 use crossbeam::channel::Receiver;
 
-use serde_json::Value;
-
 use std::io::Result;
 pub fn write_loop(
     dataset: Dataset,
     fields: Vec<String>,
-    write_rx: Receiver<Vec<Value>>,
+    relations: Vec<Relation>,
+    filter: Option<RowFilter>,
+    output: OutputConfig,
+    mut checkpoint: Option<Checkpoint>,
+    write_rx: Receiver<BlockChunk>,
 ) -> Result<()> {
-    let mut counter = 0;
+    let mut stdout = StdoutWriter::default();
+    let mut database = match &output.destination {
+        Destination::Database(path) => Some(open_database(&output, path)?),
+        _ => None,
+    };
     loop {
         //receive the bytes from stats
-        let buffer = write_rx.recv().unwrap();
-        if buffer.is_empty() {
-            break;
+        // the fetcher closes the channel when it is done, or stopped early and reports its own error
+        let chunk = match write_rx.recv() {
+            Ok(chunk) => chunk,
+            Err(_) => break,
+        };
+        // aligned spans without matching blocks only get an empty file, marking them done
+        if chunk.blocks.is_empty() && !matches!(output.destination, Destination::Directory(_)) {
+            if let Some(checkpoint) = &mut checkpoint {
+                checkpoint.record(chunk.end, Vec::new())?;
+            }
+            continue;
         }
+        let buffer = &chunk.blocks;
 
-        save_to_file(dataset, &fields, buffer, counter)?;
-        counter += 1;
+        if output.destination == Destination::Stdout {
+            stdout.write_chunk(dataset, &fields, buffer, filter.as_ref(), &output)?;
+            continue;
+        }
+        if let Some(database) = &mut database {
+            let mut frames = vec![(
+                dataset,
+                chunk_to_dataframe(dataset, &fields, buffer, filter.as_ref(), output.columns)?,
+            )];
+            for relation in &relations {
+                let df = chunk_to_dataframe(
+                    relation.dataset,
+                    &relation.fields,
+                    buffer,
+                    None,
+                    output.columns,
+                )?;
+                frames.push((relation.dataset, df));
+            }
+            database.write_chunk(&frames)?;
+            // tables keep the metadata files carry in a table of their own
+            let datasets = std::iter::once((dataset, &fields)).chain(
+                relations
+                    .iter()
+                    .map(|relation| (relation.dataset, &relation.fields)),
+            );
+            for (dataset, fields) in datasets {
+                let metadata =
+                    file_metadata(dataset, fields, chunk.start, chunk.end, buffer, &output);
+                database.write_metadata(dataset, metadata)?;
+            }
+            if let (Some(checkpoint), Destination::Database(path)) =
+                (&mut checkpoint, &output.destination)
+            {
+                checkpoint.record(chunk.end, vec![path.clone()])?;
+            }
+            continue;
+        }
+        // the filter reads the columns of the main dataset only
+        let mut files = save_to_file(dataset, &fields, &chunk, filter.as_ref(), &output)?;
+        for relation in &relations {
+            files.extend(save_to_file(
+                relation.dataset,
+                &relation.fields,
+                &chunk,
+                None,
+                &output,
+            )?);
+        }
+        if let Some(checkpoint) = &mut checkpoint {
+            let files = files
+                .iter()
+                .map(|file| file.to_string_lossy().into_owned())
+                .collect();
+            checkpoint.record(chunk.end, files)?;
+        }
+    }
+    if output.destination == Destination::Stdout {
+        stdout.finish(&output)?;
     }
     Ok(())
 }
@@ -1,13 +1,29 @@
-use reqwest::{self};
+use reqwest::{self, Client};
 
-pub async fn get_height(archive_url: &str) -> Result<String, reqwest::Error> {
+pub async fn get_height(client: &Client, archive_url: &str) -> Result<String, reqwest::Error> {
     let url = format!("{}/height", archive_url);
-    let body = reqwest::get(&url).await?.text().await?;
+    let body = client
+        .get(&url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
     Ok(body)
 }
 
-pub async fn get_worker(archive_url: &str, first_block: &str) -> Result<String, reqwest::Error> {
+pub async fn get_worker(
+    client: &Client,
+    archive_url: &str,
+    first_block: &str,
+) -> Result<String, reqwest::Error> {
     let url: String = format!("{}/{}/worker", archive_url, first_block);
-    let body = reqwest::get(&url).await?.text().await?;
+    let body = client
+        .get(&url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
     Ok(body)
 }